@@ -0,0 +1,123 @@
+use entities::Pos;
+use entities::interest::InterestConfig;
+use ggez::graphics::Rect;
+use std::time::Duration;
+
+// Players up to this size see the world at full scale.
+const ZOOM_SIZE: f32 = 2.0 * entities::START_SIZE;
+// However big the player gets, the view doesn't shrink below this.
+const MIN_ZOOM: f32 = 0.25;
+// How quickly the zoom catches up with the player's size, per second.
+const ZOOM_RATE: f32 = 4.0;
+
+// Which part of the world ends up in the window. Follows the main player and
+// shows more of the world the bigger it gets.
+pub struct Camera {
+    center: Pos,
+    zoom: f32,
+    // How far around the player the server sends anything. Zooming out
+    // further would only show empty space where there are players.
+    view_radius: f32,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera { center: Pos::default(), zoom: 1.0, view_radius: InterestConfig::default().view_radius }
+    }
+
+    // Anything but a positive radius is ignored, the zoom is limited by
+    // dividing by it.
+    pub fn set_view_radius(&mut self, view_radius: f32) {
+        if view_radius > 0.0 {
+            self.view_radius = view_radius;
+        }
+    }
+
+    // Growing by area, the view grows with the square root of the size.
+    fn zoom_for(size: f32) -> f32 {
+        (ZOOM_SIZE / size.max(ZOOM_SIZE)).sqrt().max(MIN_ZOOM)
+    }
+
+    // The smallest zoom that keeps the corners of a `width` by `height`
+    // window within the view radius.
+    fn min_zoom(&self, width: f32, height: f32) -> f32 {
+        (width * width + height * height).sqrt() / 2.0 / self.view_radius
+    }
+
+    // Center on `pos` and ease into the zoom for `size` in a window of
+    // `width` by `height` pixels, `elapsed` after the last frame.
+    pub fn follow(&mut self, pos: Pos, size: f32, width: f32, height: f32, elapsed: Duration) {
+        self.center = pos;
+        let min_zoom = self.min_zoom(width, height);
+        let target = Camera::zoom_for(size).max(min_zoom);
+        let catch_up = 1.0 - (-ZOOM_RATE * elapsed.as_secs_f32()).exp();
+        self.zoom = (self.zoom + (target - self.zoom) * catch_up).max(min_zoom);
+    }
+
+    // The world coordinates shown in a window of `width` by `height`
+    // pixels.
+    pub fn view(&self, width: f32, height: f32) -> Rect {
+        let (w, h) = (width / self.zoom, height / self.zoom);
+        Rect::new(self.center.x() - w / 2.0, self.center.y() - h / 2.0, w, h)
+    }
+
+    // Whether any of a circle shows up in `view`.
+    pub fn sees(view: &Rect, pos: Pos, radius: f32) -> bool {
+        let x = pos.x().clamp(view.x, view.x + view.w);
+        let y = pos.y().clamp(view.y, view.y + view.h);
+        let (dx, dy) = (pos.x() - x, pos.y() - y);
+        dx * dx + dy * dy <= radius * radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zoom_for() {
+        assert_eq!(1.0, Camera::zoom_for(entities::START_SIZE));
+        assert_eq!(1.0, Camera::zoom_for(ZOOM_SIZE));
+        assert_eq!(0.5, Camera::zoom_for(4.0 * ZOOM_SIZE));
+        assert_eq!(MIN_ZOOM, Camera::zoom_for(1000.0 * ZOOM_SIZE));
+    }
+
+    #[test]
+    fn test_view() {
+        let mut camera = Camera::new();
+        camera.center = Pos::new(100.0, 50.0);
+        assert_eq!(Rect::new(-300.0, -250.0, 800.0, 600.0), camera.view(800.0, 600.0));
+        camera.zoom = 0.5;
+        assert_eq!(Rect::new(-700.0, -550.0, 1600.0, 1200.0), camera.view(800.0, 600.0));
+    }
+
+    #[test]
+    fn test_view_stays_within_view_radius() {
+        let mut camera = Camera::new();
+        camera.set_view_radius(800.0);
+        camera.follow(Pos::new(0.0, 0.0), 1000.0, 800.0, 600.0, Duration::from_secs(10));
+        // 500 pixels from the centre to a corner.
+        assert!((camera.zoom - 0.625).abs() < 1e-6);
+        let view = camera.view(800.0, 600.0);
+        assert!((view.w / 2.0).hypot(view.h / 2.0) <= 800.0 + 1e-3);
+
+        // Nonsense from the server is ignored.
+        camera.set_view_radius(0.0);
+        assert_eq!(800.0, camera.view_radius);
+
+        // Small players are not affected.
+        camera.follow(Pos::new(0.0, 0.0), entities::START_SIZE, 800.0, 600.0, Duration::from_secs(10));
+        assert!((camera.zoom - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sees() {
+        let view = Rect::new(0.0, 0.0, 100.0, 50.0);
+        assert!(Camera::sees(&view, Pos::new(50.0, 25.0), 1.0));
+        // Partly in view, past an edge or a corner.
+        assert!(Camera::sees(&view, Pos::new(-5.0, 25.0), 10.0));
+        assert!(Camera::sees(&view, Pos::new(103.0, 54.0), 5.0));
+        assert!(!Camera::sees(&view, Pos::new(-15.0, 25.0), 10.0));
+        assert!(!Camera::sees(&view, Pos::new(104.0, 54.0), 5.0));
+    }
+}
@@ -0,0 +1,57 @@
+use entities::Pos;
+use ggez::graphics::{self, Color, DrawParam, FilterMode, Image};
+use ggez::graphics::spritebatch::SpriteBatch;
+use ggez::nalgebra as na;
+use ggez::{Context, GameResult};
+
+// Radius of the cached circle in pixels. Anything drawn smaller on screen is
+// scaled down and stays smooth, which covers every critter and players until
+// they are about as tall as the window. Only bigger ones are scaled up and
+// get a slightly soft edge.
+const RADIUS: u16 = 256;
+
+// A white circle with a one pixel soft edge, tinted and scaled for each
+// critter and player.
+fn circle_image(ctx: &mut Context) -> GameResult<Image> {
+    let side = 2 * RADIUS;
+    let center = f32::from(RADIUS);
+    let mut rgba = Vec::with_capacity(usize::from(side) * usize::from(side) * 4);
+    for y in 0..side {
+        for x in 0..side {
+            let (dx, dy) = (f32::from(x) + 0.5 - center, f32::from(y) + 0.5 - center);
+            let coverage = (center - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&[255, 255, 255, (coverage * 255.0) as u8]);
+        }
+    }
+    let mut image = Image::from_rgba8(ctx, side, side, &rgba)?;
+    image.set_filter(FilterMode::Linear);
+    Ok(image)
+}
+
+// Filled circles queued up during a frame and drawn in one call, instead of
+// building a mesh for each of them every frame.
+pub struct Circles {
+    batch: SpriteBatch,
+}
+
+impl Circles {
+    pub fn new(ctx: &mut Context) -> GameResult<Circles> {
+        Ok(Circles { batch: SpriteBatch::new(circle_image(ctx)?) })
+    }
+
+    // Later circles are drawn on top of earlier ones.
+    pub fn add(&mut self, pos: Pos, radius: f32, color: Color) {
+        let scale = radius / f32::from(RADIUS);
+        self.batch.add(DrawParam::new()
+            .dest(na::Point2::new(pos.x() - radius, pos.y() - radius))
+            .scale(na::Vector2::new(scale, scale))
+            .color(color));
+    }
+
+    // Draw everything added since the last call.
+    pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        graphics::draw(ctx, &self.batch, DrawParam::new())?;
+        self.batch.clear();
+        Ok(())
+    }
+}
@@ -1,142 +1,324 @@
-use ggez;
 use ggez::event;
 use ggez::graphics;
+use ggez::timer;
 use ggez::nalgebra as na;
-use entities;
 use entities::{ UDDir, LRDir };
-use serde_json;
-use serde::{Deserialize};
+use entities::frame;
+use entities::codec::Encoding;
+use entities::handshake::{Capability, Hello};
+use entities::heartbeat::{Heartbeat, HeartbeatConfig};
+use entities::interest::InterestEvent;
+use entities::snapshot::SnapshotHistory;
+use entities::world::WorldConfig;
 use ggez::{GameResult, Context};
-use std::net::TcpStream;
-use std::time::Duration;
-use std::sync::mpsc::{Sender, Receiver};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
 use std::sync::mpsc;
 use std::thread;
+use std::collections::VecDeque;
 
-const UPDATE_STEP: f32 = 4.0;
+mod camera;
+mod circles;
+
+use camera::Camera;
+use circles::Circles;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
 
 struct MainState {
     game: entities::GameWorld,
-    connection: Option<Connection>
+    connection: Option<Connection>,
+    input_tick: u32,
+    pending_inputs: VecDeque<entities::InputCommand>,
+    heartbeat: HeartbeatConfig,
+    host: String,
+    // What we say when (re)connecting, carries the latest resume token.
+    hello: Option<Hello>,
+    // Set while we are trying to get the connection back.
+    backoff: Option<Backoff>,
+    // The outcome of the attempt currently running in the background.
+    opening: Option<Receiver<Result<Connection, String>>>,
+    camera: Camera,
+    circles: Circles,
+}
+
+struct Backoff {
+    attempt: u32,
+    retry_at: Instant,
+}
+
+impl Backoff {
+    fn new(now: Instant) -> Backoff {
+        Backoff { attempt: 0, retry_at: now }
+    }
+
+    // Wait twice as long after every failed attempt, up to BACKOFF_MAX.
+    fn failed(&mut self, now: Instant) {
+        let delay = BACKOFF_BASE.checked_mul(1 << self.attempt.min(16))
+            .unwrap_or(BACKOFF_MAX)
+            .min(BACKOFF_MAX);
+        self.attempt += 1;
+        self.retry_at = now + delay;
+    }
 }
 
 struct Connection {
     socket: TcpStream,
+    reader: Option<frame::FrameReader<TcpStream>>,
+    encoding: Encoding,
+    player_id: entities::PlayerId,
+    resume_token: u64,
+    world: WorldConfig,
+    view_radius: f32,
+    heartbeat: Heartbeat,
+    // Received snapshots, the server sends deltas against them.
+    snapshots: SnapshotHistory,
     receiver: Receiver<entities::Message>,
     sender: Sender<entities::Message>,
 }
 
-fn get_players(sender: Sender<entities::Message>, socket: TcpStream) {
+fn get_players(sender: Sender<entities::Message>, mut reader: frame::FrameReader<TcpStream>, encoding: Encoding) {
     loop {
-        thread::sleep(Duration::from_millis(15));
-        let mut de = serde_json::Deserializer::from_reader(&socket);
-        if let Ok(payload) = entities::Message::deserialize(&mut de) {
-            sender.send(payload).unwrap();
+        match reader.read_frame() {
+            Ok(payload) => match encoding.decode(&payload) {
+                Ok(message) => if sender.send(message).is_err() {
+                    // The connection was dropped, nobody is listening.
+                    break
+                },
+                Err(e) => println!("Invalid message from server: {}", e),
+            },
+            Err(e) => {
+                println!("Stopped listening to server: {}", e);
+                break
+            }
         }
     }
 }
 
 impl Connection {
-    fn new(socket: TcpStream) -> Result<Connection, String> {
+    // Resolve the host, connect and say hello. Blocks for as long as the
+    // server takes to answer, so keep it away from the game loop.
+    fn open(host: &str, hello: Hello, heartbeat: HeartbeatConfig) -> Result<Connection, String> {
+        let io_error = |e: std::io::Error| format!("{:?}", e.kind());
+        let addr = host.to_socket_addrs().map_err(io_error)?
+            .next()
+            .ok_or_else(|| format!("could not resolve {}", host))?;
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(io_error)?;
+        let mut connection = Connection::new(stream, hello, heartbeat)?;
+        connection.listen();
+        Ok(connection)
+    }
+
+    // Say hello and wait for the server to either welcome or reject us.
+    fn new(socket: TcpStream, hello: Hello, heartbeat: HeartbeatConfig) -> Result<Connection, String> {
         let (sender, receiver) = mpsc::channel();
-        Ok(Connection {
-            socket,
-            sender,
-            receiver
-        })
+        let io_error = |e: std::io::Error| format!("{:?}", e.kind());
+
+        let payload = Encoding::Json.encode(&entities::Message::hello(hello)).map_err(io_error)?;
+        frame::write_frame(&mut &socket, &payload).map_err(io_error)?;
+        let mut reader = frame::FrameReader::new(socket.try_clone().map_err(io_error)?);
+        socket.set_read_timeout(Some(heartbeat.timeout)).map_err(io_error)?;
+        let answer = Encoding::Json.decode(&reader.read_frame().map_err(io_error)?).map_err(io_error)?;
+        socket.set_read_timeout(None).map_err(io_error)?;
+
+        match answer.mtype {
+            entities::MessageType::Welcome(welcome) => Ok(Connection {
+                socket,
+                reader: Some(reader),
+                encoding: welcome.encoding,
+                player_id: welcome.player_id,
+                resume_token: welcome.resume_token,
+                world: welcome.world,
+                view_radius: welcome.view_radius,
+                heartbeat: Heartbeat::new(heartbeat, Instant::now()),
+                snapshots: SnapshotHistory::new(),
+                sender,
+                receiver
+            }),
+            entities::MessageType::Rejected(reason) => Err(reason),
+            other => Err(format!("unexpected answer to hello: {:?}", other)),
+        }
     }
 
-    fn send(&self, player: &entities::Player) -> Result<(), serde_json::error::Error> {
-        let message = entities::Message::player_update(player);
-        serde_json::to_writer(&self.socket, &message)
+    fn send(&self, message: &entities::Message) -> std::io::Result<()> {
+        let payload = self.encoding.encode(message)?;
+        frame::write_frame(&mut &self.socket, &payload)
     }
 
-    fn listen(&self) {
+    fn listen(&mut self) {
         let sender_clone = self.sender.clone();
-        let socket_clone = self.socket.try_clone().unwrap();
-        thread::spawn(move || get_players(sender_clone, socket_clone));
+        let reader = self.reader.take().expect("already listening");
+        let encoding = self.encoding;
+        thread::spawn(move || get_players(sender_clone, reader, encoding));
+    }
+}
+
+// Also ends the listening thread, which holds a clone of the socket and
+// would otherwise keep it open.
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
     }
 }
 
 impl MainState {
-    fn new() -> GameResult<MainState> {
+    fn new(ctx: &mut Context) -> GameResult<MainState> {
         let s = MainState {
             game: entities::GameWorld::ggez_new()?,
-            connection: None
+            connection: None,
+            input_tick: 0,
+            pending_inputs: VecDeque::new(),
+            heartbeat: HeartbeatConfig::from_env(),
+            host: String::new(),
+            hello: None,
+            backoff: None,
+            opening: None,
+            camera: Camera::new(),
+            circles: Circles::new(ctx)?,
         };
 
         Ok(s)
     }
 
-    fn connect(&mut self, host: String, player: &entities::Player) -> Result<(), String> {
-        match TcpStream::connect(host) {
-            Ok(stream) => match Connection::new(stream) {
-                Ok(connection) => {
-                    connection.listen();
-                    connection.send(player).unwrap();
-                    self.connection = Some(connection);
-                    Ok(())
-                },
-                Err(err) => Err(err)
-            },
-            Err(e) => Err(format!("{:?}", e.kind()))
+    // The first attempt starts with the next update, and keeps being
+    // retried until one works.
+    fn connect(&mut self, host: String, hello: Hello) {
+        self.host = host;
+        self.hello = Some(hello);
+        self.backoff = Some(Backoff::new(Instant::now()));
+    }
+
+    // Connect on another thread, `update` picks up the result.
+    fn reconnect(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        let host = self.host.clone();
+        let hello = self.hello.clone().expect("connect first");
+        let heartbeat = self.heartbeat;
+        thread::spawn(move || {
+            // Nobody is left to tell if the game closed in the meantime.
+            let _ = sender.send(Connection::open(&host, hello, heartbeat));
+        });
+        self.opening = Some(receiver);
+    }
+
+    fn joined(&mut self, connection: Connection) {
+        println!("joined as {}, speaking {}", connection.player_id, connection.encoding.name());
+
+        self.game.main_player.id = connection.player_id;
+        self.game.config = connection.world;
+        self.camera.set_view_radius(connection.view_radius);
+        // Whatever we knew about the others may be stale, the server sends
+        // everything again. Unacknowledged inputs were lost with the old
+        // connection.
+        self.game.clear_players();
+        self.pending_inputs.clear();
+        if let Some(ref mut hello) = self.hello {
+            hello.resume_token = Some(connection.resume_token);
         }
+        self.connection = Some(connection);
+        self.backoff = None;
     }
 }
 
 impl event::EventHandler for MainState {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
-        let main_player = &mut self.game.main_player;
-        let mut x: f32 = 0.0;
-        let mut y: f32 = 0.0;
-        if let Some(xx) = main_player.moving.0 {
-            x = (xx as i32) as f32;
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let now = Instant::now();
+        let mut lost = false;
+
+        let retry = match self.backoff {
+            Some(ref backoff) => self.opening.is_none() && now >= backoff.retry_at,
+            None => false,
+        };
+        if retry {
+            self.reconnect();
         }
-        if let Some(yy) = main_player.moving.1 {
-            y = (yy as i32) as f32;
+        let opened = match self.opening {
+            Some(ref opening) => match opening.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => Some(Err("connecting thread died".to_string())),
+            },
+            None => None,
+        };
+        if let Some(result) = opened {
+            self.opening = None;
+            match result {
+                Ok(connection) => self.joined(connection),
+                Err(e) => {
+                    println!("Failed to connect: {}", e);
+                    if let Some(ref mut backoff) = self.backoff {
+                        backoff.failed(now);
+                    }
+                }
+            }
         }
 
-        let no_intersect = self.game.objects.iter().all(|critter| {
-            !main_player.intersect(
-                entities::Pos::new(critter.pos_x, critter.pos_y),
-                critter.size
-            )}
-        );
-        let mut new_pos_player = entities::Player::new();
-        new_pos_player.pos.move_player(
-            main_player.pos.x() + x * UPDATE_STEP,
-            main_player.pos.y() + y * UPDATE_STEP,
-        );
-        let no_future_intersect = self.game.objects.iter().all(|critter| {
-            !new_pos_player.intersect(
-                entities::Pos::new(critter.pos_x, critter.pos_y),
-                critter.size
-            )}
-        );
-        let can_move = no_intersect || no_future_intersect;
-        if can_move {
-            main_player.pos.move_player(
-                x * UPDATE_STEP,
-                y * UPDATE_STEP
-            );
+        // The server simulates movement. We send it the keys held during each
+        // tick and predict the result locally until it answers. Letting go
+        // still takes a few ticks to slow down to a stop.
+        while timer::check_update_time(ctx, entities::TICK_RATE) {
+            let moving = self.game.main_player.moving;
+            if let Some(ref connection) = self.connection {
+                if moving != (None, None) || !self.game.main_player.at_rest() {
+                    self.input_tick += 1;
+                    let command = entities::InputCommand { tick: self.input_tick, moving };
+                    lost |= connection.send(&entities::Message::input(command)).is_err();
+                    self.game.predict(&command);
+                    self.pending_inputs.push_back(command);
+                }
+            }
         }
 
         if let Some(ref mut connection) = self.connection {
-            if can_move && (x != 0.0 || y != 0.0) {
-                // TODO: this fails if the server shuts down
-                connection.send(main_player).unwrap();
+            while let Ok(message) = connection.receiver.try_recv() {
+                connection.heartbeat.seen(now);
+                match message.mtype {
+                    entities::MessageType::Snapshot(delta) => {
+                        let base = delta.base;
+                        let snapshot = match delta.apply(base.and_then(|seq| connection.snapshots.get(seq))) {
+                            Some(snapshot) => snapshot,
+                            None => {
+                                println!("Dropped a snapshot, its base {:?} is gone", base);
+                                continue
+                            }
+                        };
+                        // The server won't go back further than what it used
+                        // as the base.
+                        if let Some(seq) = base {
+                            connection.snapshots.ack(seq);
+                        }
+                        lost |= connection.send(&entities::Message::snapshot_ack(snapshot.seq)).is_err();
+                        self.game.apply_snapshot(&snapshot, &mut self.pending_inputs);
+                        connection.snapshots.push(snapshot);
+                    },
+                    entities::MessageType::PlayerLeft(id) =>
+                        self.game.remove_player(id),
+                    // The snapshot that comes with it has the player.
+                    entities::MessageType::Interest(InterestEvent::Entered(_)) => (),
+                    entities::MessageType::Interest(InterestEvent::Left(id)) =>
+                        self.game.remove_player(id),
+                    entities::MessageType::Ping(stamp) =>
+                        lost |= connection.send(&entities::Message::pong(stamp)).is_err(),
+                    entities::MessageType::Pong(stamp) =>
+                        connection.heartbeat.pong(stamp, now),
+                    _ => ()
+                }
             }
-            match connection.receiver.recv_timeout(Duration::from_millis(15)) {
-                Ok(message) => {
-                    match message.mtype {
-                        entities::MessageType::PlayerPosition =>
-                            self.game.update_player(message.player.unwrap()),
-                        entities::MessageType::WorldState =>
-                            self.game.update_world(message.world.unwrap()),
-                    }
-                },
-                _ => {  }
+            if let Some(stamp) = connection.heartbeat.ping_due(now) {
+                lost |= connection.send(&entities::Message::ping(stamp)).is_err();
             }
+            if connection.heartbeat.timed_out(now) {
+                println!("Server stopped answering");
+                lost = true;
+            }
+        }
+        if lost {
+            println!("Lost the connection, reconnecting");
+            self.connection = None;
+            self.backoff = Some(Backoff::new(now));
         }
 
         Ok(())
@@ -145,18 +327,29 @@ impl event::EventHandler for MainState {
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, [0.1, 0.2, 0.3, 1.0].into());
 
+        // Everything but the status line is drawn in world coordinates.
+        let (width, height) = graphics::drawable_size(ctx);
+        let main_player = &self.game.main_player;
+        self.camera.follow(main_player.pos, main_player.size, width, height, timer::delta(ctx));
+        let view = self.camera.view(width, height);
+        graphics::set_screen_coordinates(ctx, view)?;
+
+        let border = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::stroke(2.0),
+            graphics::Rect::new(0.0, 0.0, self.game.config.width, self.game.config.height),
+            graphics::WHITE,
+        )?;
+        graphics::draw(ctx, &border, (na::Point2::new(0.0, 0.0),))?;
+
         for critter in self.game.objects.iter() {
-            let intersect = self.game.main_player.intersect(
-                entities::Pos::new(critter.pos_x, critter.pos_y),
-                critter.size
-                );
+            let pos = entities::Pos::new(critter.pos_x, critter.pos_y);
+            if !Camera::sees(&view, pos, critter.size as f32) {
+                continue;
+            }
+            let intersect = self.game.main_player.intersect(pos, critter.size);
             let color = if intersect {
-                graphics::Color::new(
-                    255.0,
-                    255.0,
-                    255.0,
-                    critter.color.3
-                    )
+                graphics::Color::new(1.0, 1.0, 1.0, critter.color.3)
             } else {
                 graphics::Color::new(
                     critter.color.0,
@@ -165,38 +358,43 @@ impl event::EventHandler for MainState {
                     critter.color.3
                     )
             };
-            let circle = graphics::Mesh::new_circle(
-                ctx,
-                graphics::DrawMode::fill(),
-                na::Point2::new(critter.pos_x, critter.pos_y),
-                critter.size as f32,
-                2.0,
-                color
-            )?;
-            graphics::draw(ctx, &circle, (na::Point2::new(0.0, 0.0),))?;
+            self.circles.add(pos, critter.size as f32, color);
         }
 
+        // Other players are drawn slightly in the past, in between two of the
+        // positions received from the server.
+        let render_time = Instant::now() - entities::interpolation::INTERPOLATION_DELAY;
         for player in self.game.players.iter() {
-            let circle = graphics::Mesh::new_circle(
-                ctx,
-                graphics::DrawMode::fill(),
-                na::Point2::new(player.pos.x(), player.pos.y()),
-                player.size as f32,
-                2.0,
-                graphics::WHITE,
-            )?;
-            graphics::draw(ctx, &circle, (na::Point2::new(0.0, 0.0),))?;
+            let pos = self.game.interpolated_pos(player, render_time);
+            if !Camera::sees(&view, pos, player.size) {
+                continue;
+            }
+            self.circles.add(pos, player.size, graphics::WHITE);
         }
 
-        let circle = graphics::Mesh::new_circle(
-            ctx,
-            graphics::DrawMode::fill(),
-            na::Point2::new(self.game.main_player.pos.x(), self.game.main_player.pos.y()),
-            self.game.main_player.size as f32,
-            2.0,
-            graphics::WHITE,
-        )?;
-        graphics::draw(ctx, &circle, (na::Point2::new(0.0, 0.0),))?;
+        self.circles.add(self.game.main_player.pos, self.game.main_player.size, graphics::WHITE);
+        self.circles.draw(ctx)?;
+
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))?;
+        let status = match (&self.connection, &self.backoff) {
+            (Some(connection), _) => match connection.heartbeat.rtt {
+                Some(rtt) => format!("ping {} ms", rtt.as_millis()),
+                None => "ping -".to_string(),
+            },
+            (None, Some(backoff)) if self.opening.is_some() =>
+                format!("connecting, attempt {}", backoff.attempt + 1),
+            (None, Some(backoff)) => format!(
+                "connection lost, retry {} in {:.1} s",
+                backoff.attempt + 1,
+                backoff.retry_at.saturating_duration_since(Instant::now()).as_secs_f32()
+            ),
+            (None, None) => "offline".to_string(),
+        };
+        // Averaged over the last few frames, to keep an eye on with many
+        // critters around.
+        let frame_time = timer::average_delta(ctx).as_secs_f32() * 1000.0;
+        let status = format!("{}, {:.1} ms/frame", status, frame_time);
+        graphics::draw(ctx, &graphics::Text::new(status), (na::Point2::new(10.0, 10.0),))?;
         graphics::present(ctx)?;
 
         Ok(())
@@ -209,7 +407,7 @@ impl event::EventHandler for MainState {
         _keymod: event::KeyMods,
         _repeat: bool,
     ) {
-        let mut main_player = &mut self.game.main_player;
+        let main_player = &mut self.game.main_player;
         main_player.save_prev_move();
         match keycode {
             event::KeyCode::Up => {
@@ -229,7 +427,7 @@ impl event::EventHandler for MainState {
     }
 
     fn key_up_event(&mut self, _ctx: &mut ggez::Context, keycode: event::KeyCode, _keymod: event::KeyMods) {
-        let mut main_player = &mut self.game.main_player;
+        let main_player = &mut self.game.main_player;
         match (keycode, main_player.moving) {
             (event::KeyCode::Up, (_, Some(UDDir::Up))) => {
                 main_player.moving = (main_player.moving.0, None);
@@ -249,15 +447,23 @@ impl event::EventHandler for MainState {
 }
 
 pub fn main() -> ggez::GameResult {
-    let state = &mut MainState::new().unwrap();
     let cb = ggez::ContextBuilder::new("super_simple", "ggez");
     let (ctx, event_loop) = &mut cb.build()?;
+    let state = &mut MainState::new(ctx)?;
 
-    println!("connect attempt");
-    match state.connect("127.0.0.1:3012".to_string(), &state.game.main_player.clone()) {
-        Ok(_) => println!("connected"),
-        Err(e) => println!("Failed to connect: {}", e)
+    // RUGAR_ENCODING=json makes the traffic readable when debugging.
+    let encodings = match std::env::var("RUGAR_ENCODING").ok().and_then(|name| Encoding::from_name(&name)) {
+        Some(encoding) => vec![encoding],
+        None => vec![Encoding::Binary, Encoding::Json],
     };
+    let capabilities = encodings.into_iter().map(Capability::Encoding).collect();
+    // RUGAR_NAME picks the name shown to other players.
+    if let Ok(name) = std::env::var("RUGAR_NAME") {
+        state.game.main_player.set_name(&name);
+    }
+    let hello = Hello::new(&state.game.main_player.name, capabilities);
+
+    state.connect("127.0.0.1:3012".to_string(), hello);
 
     event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,60 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use entities::spatial::SpatialGrid;
+use entities::Pos;
+use rand::Rng;
+
+const CRITTERS: u32 = 10_000;
+// Side of the square the critters are spread over.
+const WORLD: f32 = 10_000.0;
+
+fn critters() -> Vec<(u32, Pos, f32)> {
+    let mut rng = rand::thread_rng();
+    (0..CRITTERS)
+        .map(|key| {
+            let pos = Pos::new(rng.gen_range(0.0, WORLD), rng.gen_range(0.0, WORLD));
+            (key, pos, 10.0 * rng.gen_range(1, 10) as f32)
+        })
+        .collect()
+}
+
+fn build(critters: &[(u32, Pos, f32)]) -> SpatialGrid<u32> {
+    let mut grid = SpatialGrid::default();
+    for &(key, pos, radius) in critters.iter() {
+        grid.insert(key, pos, radius);
+    }
+    grid
+}
+
+fn bench_spatial(c: &mut Criterion) {
+    let critters = critters();
+    let grid = build(&critters);
+    let center = Pos::new(WORLD / 2.0, WORLD / 2.0);
+
+    c.bench_function("build 10k", |b| b.iter(|| build(black_box(&critters))));
+    c.bench_function("query circle 10k", |b| {
+        b.iter(|| grid.query_circle(black_box(center), 20.0))
+    });
+    // What Player::step did before, for comparison.
+    c.bench_function("linear scan 10k", |b| {
+        b.iter(|| {
+            critters.iter()
+                .filter(|&&(_, pos, radius)| {
+                    let (dx, dy) = (pos.x() - center.x(), pos.y() - center.y());
+                    dx * dx + dy * dy <= (20.0 + radius) * (20.0 + radius)
+                })
+                .count()
+        })
+    });
+    c.bench_function("nearest 10k", |b| b.iter(|| grid.nearest(black_box(center))));
+    c.bench_function("move 10k", |b| {
+        let mut grid = grid.clone();
+        b.iter(|| {
+            for &(key, pos, radius) in critters.iter() {
+                grid.insert(key, Pos::new(pos.x() + 1.0, pos.y()), radius);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_spatial);
+criterion_main!(benches);
@@ -0,0 +1,130 @@
+use crate::{Critter, Player, TICK_DT};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::f32::consts::PI;
+
+// In pixels per second, like the players.
+pub const WANDER_SPEED: f32 = 30.0;
+pub const FLEE_SPEED: f32 = 75.0;
+pub const CHASE_SPEED: f32 = 60.0;
+// How close a player has to be for a critter to notice it.
+pub const SENSE_RADIUS: f32 = 200.0;
+// How far a wandering critter may turn in one tick.
+const WANDER_TURN: f32 = PI / 16.0;
+// How far from home a wandering critter goes before heading back.
+pub const WANDER_RADIUS: f32 = 150.0;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq)]
+pub enum Behaviour {
+    // Stays where it was spawned.
+    #[default]
+    Idle,
+    Wander,
+    // Runs from players big enough to eat it, wanders otherwise.
+    Flee,
+    // Goes after players smaller than itself, wanders otherwise.
+    Chase,
+}
+
+fn scaled(x: f32, y: f32, length: f32) -> (f32, f32) {
+    let norm = (x * x + y * y).sqrt();
+    if norm == 0.0 {
+        return (0.0, 0.0);
+    }
+    (x / norm * length, y / norm * length)
+}
+
+fn wander(critter: &Critter) -> (f32, f32) {
+    let (dx, dy) = (critter.home.x() - critter.pos_x, critter.home.y() - critter.pos_y);
+    if dx * dx + dy * dy > WANDER_RADIUS * WANDER_RADIUS {
+        return scaled(dx, dy, WANDER_SPEED);
+    }
+    let mut rng = rand::thread_rng();
+    let heading = if critter.vel_x == 0.0 && critter.vel_y == 0.0 {
+        rng.gen_range(-PI, PI)
+    } else {
+        critter.vel_y.atan2(critter.vel_x) + rng.gen_range(-WANDER_TURN, WANDER_TURN)
+    };
+    (heading.cos() * WANDER_SPEED, heading.sin() * WANDER_SPEED)
+}
+
+// The critter's velocity for the next tick, given the closest player.
+pub fn steer(critter: &Critter, nearest: Option<&Player>) -> (f32, f32) {
+    let size = critter.size as f32;
+    let target = nearest.and_then(|player| {
+        let (dx, dy) = (player.pos.x() - critter.pos_x, player.pos.y() - critter.pos_y);
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance - player.size <= SENSE_RADIUS {
+            Some((player, dx, dy, distance))
+        } else {
+            None
+        }
+    });
+
+    match (critter.behaviour, target) {
+        (Behaviour::Idle, _) => (0.0, 0.0),
+        (Behaviour::Flee, Some((player, dx, dy, _))) if player.can_eat(size) =>
+            scaled(-dx, -dy, FLEE_SPEED),
+        // Stop at arm's length, a critter can't eat anyone and shouldn't
+        // pin players in place either.
+        (Behaviour::Chase, Some((player, dx, dy, distance))) if player.size < size => {
+            let gap = distance - player.size - size - 1.0;
+            scaled(dx, dy, (gap / TICK_DT).clamp(0.0, CHASE_SPEED))
+        },
+        _ => wander(critter),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+    use crate::tests::{critter_at, sized_player};
+
+    fn speed((x, y): (f32, f32)) -> f32 {
+        (x * x + y * y).sqrt()
+    }
+
+    #[test]
+    fn test_idle() {
+        let c = Critter { behaviour: Behaviour::Idle, ..critter_at(1, Pos::default(), 5) };
+        assert_eq!((0.0, 0.0), steer(&c, Some(&sized_player(1, Pos::new(20.0, 0.0), 10.0))));
+    }
+
+    #[test]
+    fn test_flee() {
+        let c = Critter { behaviour: Behaviour::Flee, ..critter_at(1, Pos::default(), 5) };
+        assert_eq!((-FLEE_SPEED, 0.0), steer(&c, Some(&sized_player(1, Pos::new(50.0, 0.0), 10.0))));
+        // Too small to be a threat, or too far away to notice.
+        let small = sized_player(1, Pos::new(50.0, 0.0), 5.0);
+        let far = sized_player(1, Pos::new(500.0, 0.0), 10.0);
+        assert!((speed(steer(&c, Some(&small))) - WANDER_SPEED).abs() < 1e-5);
+        assert!((speed(steer(&c, Some(&far))) - WANDER_SPEED).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_chase() {
+        let c = Critter { behaviour: Behaviour::Chase, ..critter_at(1, Pos::default(), 40) };
+        assert_eq!((CHASE_SPEED, 0.0), steer(&c, Some(&sized_player(1, Pos::new(100.0, 0.0), 10.0))));
+        // Just fast enough to close the gap in one tick.
+        let (x, y) = steer(&c, Some(&sized_player(1, Pos::new(51.5, 0.0), 10.0)));
+        assert!((x * TICK_DT - 0.5).abs() < 1e-5);
+        assert_eq!(0.0, y);
+        assert_eq!((0.0, 0.0), steer(&c, Some(&sized_player(1, Pos::new(45.0, 0.0), 10.0))));
+        let big = sized_player(1, Pos::new(100.0, 0.0), 50.0);
+        assert!((speed(steer(&c, Some(&big))) - WANDER_SPEED).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_wander_without_players() {
+        let c = Critter { behaviour: Behaviour::Chase, ..critter_at(1, Pos::default(), 5) };
+        assert!((speed(steer(&c, None)) - WANDER_SPEED).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_wander_home() {
+        // Home is where it was spawned, the origin here.
+        let c = Critter { behaviour: Behaviour::Wander, ..critter_at(1, Pos::new(200.0, 0.0), 5) };
+        assert_eq!((-WANDER_SPEED, 0.0), steer(&c, None));
+    }
+}
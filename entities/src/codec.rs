@@ -0,0 +1,90 @@
+use crate::Message;
+use serde::{Serialize, Deserialize};
+use std::io;
+
+// How messages are turned into frame payloads. JSON is easy to read when
+// debugging, binary is what clients should normally use.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub enum Encoding {
+    Json,
+    #[default]
+    Binary,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Binary => "binary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "binary" => Some(Encoding::Binary),
+            _ => None
+        }
+    }
+
+    pub fn encode(self, message: &Message) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec(message)?),
+            Encoding::Binary => bincode::serialize(message).map_err(invalid_data),
+        }
+    }
+
+    pub fn decode(self, payload: &[u8]) -> io::Result<Message> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_slice(payload)?),
+            Encoding::Binary => bincode::deserialize(payload).map_err(invalid_data),
+        }
+    }
+}
+
+fn invalid_data(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameWorld, InputCommand, LRDir, MessageType, Pos};
+    use crate::tests::critter_at;
+
+    #[test]
+    fn test_names() {
+        for encoding in [Encoding::Json, Encoding::Binary].iter() {
+            assert_eq!(Some(*encoding), Encoding::from_name(encoding.name()));
+        }
+        assert_eq!(None, Encoding::from_name("xml"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let command = InputCommand { tick: 7, moving: (Some(LRDir::Left), None) };
+        for encoding in [Encoding::Json, Encoding::Binary].iter() {
+            let payload = encoding.encode(&Message::input(command)).unwrap();
+            match encoding.decode(&payload).unwrap().mtype {
+                MessageType::Input(decoded) => assert_eq!(command, decoded),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_is_smaller() {
+        let mut world = GameWorld::new();
+        world.update_world((1..=10).map(|id| critter_at(id, Pos::new(10.0 * id as f32, 50.0), 5)).collect());
+        let message = Message::snapshot(world.snapshot(1).delta(None));
+        let json = Encoding::Json.encode(&message).unwrap();
+        let binary = Encoding::Binary.encode(&message).unwrap();
+        assert!(binary.len() < json.len());
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        assert!(Encoding::Json.decode(b"{").is_err());
+        assert!(Encoding::Binary.decode(&[0xff]).is_err());
+    }
+}
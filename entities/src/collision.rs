@@ -0,0 +1,130 @@
+use crate::Pos;
+
+// Overlaps left after this many passes over the obstacles are left for the
+// next step.
+const ITERATIONS: usize = 4;
+
+// How a circle overlaps another one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Contact {
+    // Unit vector from the other circle's centre towards this one.
+    pub normal: (f32, f32),
+    // How far this circle has to move along `normal` to only touch.
+    pub depth: f32,
+}
+
+// The contact between the circle at `pos` and the one at `other`, if they
+// overlap. Touching is not overlapping.
+pub fn contact(pos: Pos, radius: f32, other: Pos, other_radius: f32) -> Option<Contact> {
+    let reach = radius + other_radius;
+    let (dx, dy) = (pos.x() - other.x(), pos.y() - other.y());
+    let distance_2 = dx * dx + dy * dy;
+    if distance_2 >= reach * reach {
+        return None;
+    }
+    let distance = distance_2.sqrt();
+    // Right on top of each other, any way out will do as long as it is the
+    // same one everywhere.
+    let normal = if distance == 0.0 {
+        (1.0, 0.0)
+    } else {
+        (dx / distance, dy / distance)
+    };
+    Some(Contact { normal, depth: reach - distance })
+}
+
+// Push the circle at `pos` out of the `obstacles`, given as centre and
+// radius, one after another along each contact normal. The result only
+// depends on the input and the order of the obstacles.
+pub fn resolve(pos: Pos, radius: f32, obstacles: &[(Pos, f32)]) -> Pos {
+    let mut pos = pos;
+    for _ in 0..ITERATIONS {
+        let mut moved = false;
+        for (other, other_radius) in obstacles.iter() {
+            if let Some(contact) = contact(pos, radius, *other, *other_radius) {
+                pos.move_player(contact.normal.0 * contact.depth, contact.normal.1 * contact.depth);
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    pos
+}
+
+// Drop the part of `velocity` going into the contact, what is left slides
+// along the edge. Moving away is left alone.
+pub fn slide(velocity: (f32, f32), normal: (f32, f32)) -> (f32, f32) {
+    let into = velocity.0 * normal.0 + velocity.1 * normal.1;
+    if into >= 0.0 {
+        return velocity;
+    }
+    (velocity.0 - into * normal.0, velocity.1 - into * normal.1)
+}
+
+// Move two overlapping circles apart until they only touch. Each one moves
+// by the other's share of the total mass, so the heavier one hardly moves.
+pub fn push_apart(a: (Pos, f32, f32), b: (Pos, f32, f32)) -> Option<(Pos, Pos)> {
+    let ((mut a_pos, a_radius, a_mass), (mut b_pos, b_radius, b_mass)) = (a, b);
+    let contact = contact(a_pos, a_radius, b_pos, b_radius)?;
+    let share = b_mass / (a_mass + b_mass);
+    let (x, y) = (contact.normal.0 * contact.depth, contact.normal.1 * contact.depth);
+    a_pos.move_player(x * share, y * share);
+    b_pos.move_player(-x * (1.0 - share), -y * (1.0 - share));
+    Some((a_pos, b_pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact() {
+        let origin = Pos::new(0.0, 0.0);
+        assert_eq!(None, contact(Pos::new(20.0, 0.0), 10.0, origin, 10.0));
+        assert_eq!(
+            Some(Contact { normal: (0.6, 0.8), depth: 5.0 }),
+            contact(Pos::new(6.0, 8.0), 10.0, origin, 5.0)
+        );
+        assert_eq!(
+            Some(Contact { normal: (1.0, 0.0), depth: 20.0 }),
+            contact(origin, 10.0, origin, 10.0)
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let pos = resolve(Pos::new(15.0, 0.0), 10.0, &[(Pos::new(0.0, 0.0), 10.0)]);
+        assert_eq!(Pos::new(20.0, 0.0), pos);
+
+        // Wedged in between two, ends up touching both.
+        let obstacles = [(Pos::new(-15.0, -20.0), 10.0), (Pos::new(15.0, -20.0), 10.0)];
+        let pos = resolve(Pos::new(0.0, -10.0), 15.0, &obstacles);
+        for (other, radius) in obstacles.iter() {
+            let mut pos = pos;
+            assert!(pos.object_distance(*other) >= radius + 15.0 - 1e-3);
+        }
+        assert_eq!(pos, resolve(Pos::new(0.0, -10.0), 15.0, &obstacles));
+    }
+
+    #[test]
+    fn test_push_apart() {
+        let origin = Pos::new(0.0, 0.0);
+        assert_eq!(None, push_apart((origin, 10.0, 1.0), (Pos::new(30.0, 0.0), 10.0, 1.0)));
+        assert_eq!(
+            Some((Pos::new(-5.0, 0.0), Pos::new(15.0, 0.0))),
+            push_apart((origin, 10.0, 1.0), (Pos::new(10.0, 0.0), 10.0, 1.0))
+        );
+        assert_eq!(
+            Some((Pos::new(-2.0, 0.0), Pos::new(18.0, 0.0))),
+            push_apart((origin, 10.0, 4.0), (Pos::new(10.0, 0.0), 10.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn test_slide() {
+        assert_eq!((0.0, 3.0), slide((-2.0, 3.0), (1.0, 0.0)));
+        assert_eq!((2.0, 3.0), slide((2.0, 3.0), (1.0, 0.0)));
+    }
+}
@@ -0,0 +1,160 @@
+use byteorder::{BigEndian, ByteOrder};
+use std::io;
+use std::io::{Read, Write};
+
+// Every message on the wire is a big endian u32 payload length followed by
+// the payload itself.
+pub const HEADER_LEN: usize = 4;
+// Anything longer is treated as a corrupt stream rather than allocated.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; HEADER_LEN + payload.len()];
+    BigEndian::write_u32(&mut frame[..HEADER_LEN], payload.len() as u32);
+    frame[HEADER_LEN..].copy_from_slice(payload);
+    frame
+}
+
+// Header and payload go out in a single write so frames written to the same
+// socket from different threads do not interleave.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode(payload))
+}
+
+// Splits a byte stream back into frames, no matter how the bytes were cut up
+// by the reads that produced them.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder { buffer: vec![] }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = BigEndian::read_u32(&self.buffer[..HEADER_LEN]) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {} bytes exceeds the limit", len),
+            ));
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let frame = self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.buffer.drain(..HEADER_LEN + len);
+        Ok(Some(frame))
+    }
+}
+
+// Blocking frame reader on top of a socket.
+pub struct FrameReader<R: Read> {
+    reader: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> FrameReader<R> {
+        FrameReader { reader, decoder: FrameDecoder::new() }
+    }
+
+    // Blocks until a whole frame is available. Fails with `UnexpectedEof`
+    // once the other side closed the connection.
+    pub fn read_frame(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = [0; 4096];
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(frame);
+            }
+            let n = self.reader.read(&mut buf)?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            self.decoder.extend(&buf[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out the underlying bytes a few at a time, like a slow socket.
+    struct Trickle {
+        data: Vec<u8>,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(vec![0, 0, 0, 3, 1, 2, 3], encode(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_fragmented() {
+        let frame = encode(b"hello");
+        let mut decoder = FrameDecoder::new();
+        for byte in frame.iter() {
+            assert!(decoder.next_frame().unwrap().is_none());
+            decoder.extend(&[*byte]);
+        }
+        assert_eq!(b"hello".to_vec(), decoder.next_frame().unwrap().unwrap());
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_coalesced() {
+        let mut data = encode(b"one");
+        data.extend(encode(b""));
+        data.extend(encode(b"three"));
+        data.extend(&encode(b"four")[..5]);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&data);
+        assert_eq!(b"one".to_vec(), decoder.next_frame().unwrap().unwrap());
+        assert_eq!(b"".to_vec(), decoder.next_frame().unwrap().unwrap());
+        assert_eq!(b"three".to_vec(), decoder.next_frame().unwrap().unwrap());
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.extend(b"our");
+        assert_eq!(b"four".to_vec(), decoder.next_frame().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut data = encode(b"first");
+        data.extend(encode(b"second"));
+        let mut reader = FrameReader::new(Trickle { data, chunk: 3 });
+        assert_eq!(b"first".to_vec(), reader.read_frame().unwrap());
+        assert_eq!(b"second".to_vec(), reader.read_frame().unwrap());
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            reader.read_frame().unwrap_err().kind()
+        );
+    }
+}
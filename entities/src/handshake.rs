@@ -0,0 +1,127 @@
+use crate::PlayerId;
+use crate::codec::Encoding;
+use crate::world::WorldConfig;
+use serde::{Serialize, Deserialize};
+
+// Bump whenever a change to the messages breaks older builds.
+pub const PROTOCOL_VERSION: u32 = 13;
+// Longer display names are cut off by the server.
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Capability {
+    Encoding(Encoding),
+}
+
+// First message of every connection, always sent as JSON so that any build
+// of the server can read it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub name: String,
+    pub capabilities: Vec<Capability>,
+    // Token from an earlier `Welcome`, to get the same player back after
+    // losing the connection.
+    pub resume_token: Option<u64>,
+}
+
+// The server's answer to an acceptable `Hello`, also sent as JSON. Everything
+// after it uses `encoding`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Welcome {
+    pub version: u32,
+    pub encoding: Encoding,
+    pub player_id: PlayerId,
+    pub resume_token: u64,
+    pub world: WorldConfig,
+    // How far around its player the client gets to see anything, it
+    // shouldn't zoom out any further.
+    pub view_radius: f32,
+}
+
+impl Hello {
+    pub fn new(name: &str, capabilities: Vec<Capability>) -> Hello {
+        Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+            capabilities,
+            resume_token: None,
+        }
+    }
+
+    pub fn display_name(&self) -> String {
+        self.name.trim().chars().take(MAX_NAME_LEN).collect()
+    }
+
+    fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+// Decide how to talk to a client, or why we can't.
+pub fn negotiate(
+    hello: &Hello,
+    player_id: PlayerId,
+    resume_token: u64,
+    world: WorldConfig,
+    view_radius: f32,
+) -> Result<Welcome, String> {
+    if hello.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "protocol version {} is not supported, the server speaks {}",
+            hello.version, PROTOCOL_VERSION
+        ));
+    }
+    let encoding = [Encoding::Binary, Encoding::Json].iter()
+        .copied()
+        .find(|encoding| hello.supports(Capability::Encoding(*encoding)));
+    match encoding {
+        Some(encoding) => Ok(Welcome {
+            version: PROTOCOL_VERSION,
+            encoding,
+            player_id,
+            resume_token,
+            world,
+            view_radius,
+        }),
+        None => Err("no supported encoding".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefers_binary() {
+        let hello = Hello::new("a", vec![
+            Capability::Encoding(Encoding::Json),
+            Capability::Encoding(Encoding::Binary),
+        ]);
+        assert_eq!(Encoding::Binary, negotiate(&hello, 1, 0, WorldConfig::default(), 800.0).unwrap().encoding);
+    }
+
+    #[test]
+    fn test_json_only() {
+        let hello = Hello::new("a", vec![Capability::Encoding(Encoding::Json)]);
+        assert_eq!(Encoding::Json, negotiate(&hello, 1, 0, WorldConfig::default(), 800.0).unwrap().encoding);
+    }
+
+    #[test]
+    fn test_rejects_other_version() {
+        let mut hello = Hello::new("a", vec![Capability::Encoding(Encoding::Json)]);
+        hello.version = PROTOCOL_VERSION + 1;
+        assert!(negotiate(&hello, 1, 0, WorldConfig::default(), 800.0).is_err());
+    }
+
+    #[test]
+    fn test_display_name() {
+        let hello = Hello::new(&format!("  {}  ", "x".repeat(100)), vec![]);
+        assert_eq!(MAX_NAME_LEN, hello.display_name().len());
+    }
+
+    #[test]
+    fn test_rejects_without_encoding() {
+        assert!(negotiate(&Hello::new("a", vec![]), 1, 0, WorldConfig::default(), 800.0).is_err());
+    }
+}
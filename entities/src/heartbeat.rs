@@ -0,0 +1,142 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HeartbeatConfig {
+    // How often to ping the other side.
+    pub interval: Duration,
+    // How long the other side may stay silent before we give up on it.
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    // RUGAR_PING_INTERVAL_MS and RUGAR_TIMEOUT_MS override the defaults, zero
+    // is ignored.
+    pub fn from_env() -> HeartbeatConfig {
+        let millis = |name: &str| env::var(name).ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|value: &u64| *value > 0)
+            .map(Duration::from_millis);
+        let default = HeartbeatConfig::default();
+        HeartbeatConfig {
+            interval: millis("RUGAR_PING_INTERVAL_MS").unwrap_or(default.interval),
+            timeout: millis("RUGAR_TIMEOUT_MS").unwrap_or(default.timeout),
+        }
+    }
+}
+
+// Keeps track of one connection's liveness. Pings carry a stamp in
+// milliseconds since `epoch` which the other side sends back unchanged, so
+// only our own clock is involved in measuring the round trip.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    epoch: Instant,
+    last_ping: Option<Instant>,
+    last_seen: Instant,
+    pub rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig, now: Instant) -> Heartbeat {
+        Heartbeat {
+            config,
+            epoch: now,
+            last_ping: None,
+            last_seen: now,
+            rtt: None,
+        }
+    }
+
+    // Any message counts as a sign of life.
+    pub fn seen(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    // The stamp to send in a ping, if one is due.
+    pub fn ping_due(&mut self, now: Instant) -> Option<u64> {
+        match self.last_ping {
+            Some(last) if now.duration_since(last) < self.config.interval => None,
+            _ => {
+                self.last_ping = Some(now);
+                Some(now.duration_since(self.epoch).as_millis() as u64)
+            }
+        }
+    }
+
+    pub fn pong(&mut self, stamp: u64, now: Instant) {
+        match self.epoch.checked_add(Duration::from_millis(stamp)) {
+            Some(sent) if sent <= now => self.rtt = Some(now.duration_since(sent)),
+            _ => ()
+        }
+    }
+
+    pub fn timed_out(&self, now: Instant) -> bool {
+        now.duration_since(self.last_seen) > self.config.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    #[test]
+    fn test_from_env_ignores_zero() {
+        env::set_var("RUGAR_PING_INTERVAL_MS", "0");
+        env::set_var("RUGAR_TIMEOUT_MS", "0");
+        assert_eq!(HeartbeatConfig::default(), HeartbeatConfig::from_env());
+        env::set_var("RUGAR_TIMEOUT_MS", "250");
+        assert_eq!(Duration::from_millis(250), HeartbeatConfig::from_env().timeout);
+        env::remove_var("RUGAR_PING_INTERVAL_MS");
+        env::remove_var("RUGAR_TIMEOUT_MS");
+    }
+
+    #[test]
+    fn test_ping_interval() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(config(), start);
+        assert_eq!(Some(0), heartbeat.ping_due(start));
+        assert_eq!(None, heartbeat.ping_due(start + Duration::from_millis(50)));
+        assert_eq!(Some(100), heartbeat.ping_due(start + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_rtt() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(config(), start);
+        let stamp = heartbeat.ping_due(start + Duration::from_millis(10)).unwrap();
+        heartbeat.pong(stamp, start + Duration::from_millis(52));
+        assert_eq!(Some(Duration::from_millis(42)), heartbeat.rtt);
+
+        // A stamp from the future is bogus and ignored.
+        heartbeat.pong(10_000, start + Duration::from_millis(60));
+        heartbeat.pong(u64::MAX, start + Duration::from_millis(60));
+        assert_eq!(Some(Duration::from_millis(42)), heartbeat.rtt);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(config(), start);
+        assert!(!heartbeat.timed_out(start + Duration::from_millis(400)));
+        heartbeat.seen(start + Duration::from_millis(400));
+        assert!(!heartbeat.timed_out(start + Duration::from_millis(800)));
+        assert!(heartbeat.timed_out(start + Duration::from_millis(901)));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::InputCommand;
+use std::collections::VecDeque;
+
+// Commands a client may get ahead of the server before the oldest ones are
+// dropped. Anything older would only add lag.
+pub const MAX_QUEUED_INPUTS: usize = 8;
+// How many commands a player may catch up on in one tick after some were
+// late. Each command simulates one tick, so on average a player never moves
+// faster than the server's clock.
+pub const INPUT_BURST: u32 = 3;
+
+// One player's commands waiting for the server tick.
+#[derive(Debug, Clone, Default)]
+pub struct InputQueue {
+    commands: VecDeque<InputCommand>,
+    // Ticks the player is owed, earned one per tick and spent one per
+    // command applied.
+    budget: u32,
+}
+
+impl InputQueue {
+    pub fn new() -> InputQueue {
+        InputQueue { commands: VecDeque::new(), budget: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn push(&mut self, command: InputCommand) {
+        if self.commands.len() == MAX_QUEUED_INPUTS {
+            self.commands.pop_front();
+        }
+        self.commands.push_back(command);
+    }
+
+    // The commands to apply this tick, call once per tick.
+    pub fn take(&mut self) -> Vec<InputCommand> {
+        self.budget = (self.budget + 1).min(INPUT_BURST);
+        let count = self.commands.len().min(self.budget as usize);
+        self.budget -= count as u32;
+        self.commands.drain(..count).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::player_at;
+    use crate::{GameWorld, LRDir, Pos, MAX_SPEED, TICK_DT};
+
+    fn command(tick: u32) -> InputCommand {
+        InputCommand { tick, moving: (Some(LRDir::Right), None) }
+    }
+
+    #[test]
+    fn test_drops_oldest() {
+        let mut queue = InputQueue::new();
+        for tick in 1..=20 {
+            queue.push(command(tick));
+        }
+        assert_eq!(MAX_QUEUED_INPUTS, queue.len());
+        assert_eq!(vec![command(13)], queue.take());
+    }
+
+    #[test]
+    fn test_catches_up_after_late_commands() {
+        let mut queue = InputQueue::new();
+        assert!(queue.take().is_empty());
+        assert!(queue.take().is_empty());
+        for tick in 1..=5 {
+            queue.push(command(tick));
+        }
+        assert_eq!(INPUT_BURST as usize, queue.take().len());
+        assert_eq!(1, queue.take().len());
+        assert_eq!(1, queue.take().len());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_flooding_does_not_speed_up() {
+        let mut honest = GameWorld::new();
+        honest.add_player(player_at(1, Pos::new(100.0, 100.0)));
+        let mut flooding = honest.clone();
+        let (mut queue, mut next) = (InputQueue::new(), 1);
+        let ticks = 60;
+        for tick in 1..=ticks {
+            honest.apply_input(1, &command(tick));
+            // Four commands for every tick.
+            for _ in 0..4 {
+                queue.push(command(next));
+                next += 1;
+            }
+            for command in queue.take() {
+                flooding.apply_input(1, &command);
+            }
+            assert!(queue.len() <= MAX_QUEUED_INPUTS);
+        }
+        let ahead = flooding.players[0].pos.x() - honest.players[0].pos.x();
+        assert!(ahead <= MAX_SPEED * TICK_DT + 1e-3, "{} ahead", ahead);
+    }
+}
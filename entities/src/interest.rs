@@ -0,0 +1,138 @@
+use crate::{GameWorld, PlayerId};
+use crate::snapshot::Snapshot;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::env;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InterestConfig {
+    // How far around its player a client gets to see anything.
+    pub view_radius: f32,
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        InterestConfig { view_radius: 800.0 }
+    }
+}
+
+impl InterestConfig {
+    // RUGAR_VIEW_RADIUS overrides the default.
+    pub fn from_env() -> InterestConfig {
+        let default = InterestConfig::default();
+        InterestConfig {
+            view_radius: env::var("RUGAR_VIEW_RADIUS").ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value: &f32| *value > 0.0)
+                .unwrap_or(default.view_radius),
+        }
+    }
+}
+
+// Another player coming into or going out of a client's view.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum InterestEvent {
+    Entered(PlayerId),
+    Left(PlayerId),
+}
+
+// The other players one client currently sees.
+#[derive(Debug, Default)]
+pub struct Interest {
+    visible: HashSet<PlayerId>,
+}
+
+impl Interest {
+    pub fn new() -> Interest {
+        Interest { visible: HashSet::new() }
+    }
+
+    // Snapshot `seq` of what in `world` shows up within `radius` of player
+    // `id`, even partly, and who came into or went out of view since the
+    // last call. The player itself is always in it.
+    pub fn filter(&mut self, world: &GameWorld, seq: u32, id: PlayerId, radius: f32) -> (Snapshot, Vec<InterestEvent>) {
+        let center = match world.players.iter().find(|x| x.id == id) {
+            Some(player) => player.pos,
+            None => return (Snapshot { seq, ..Snapshot::default() }, vec![]),
+        };
+        // The grids come in no particular order, keep the one of the world.
+        let mut players: Vec<_> = world.players_in(center, radius).into_iter().cloned().collect();
+        players.sort_by_key(|x| x.id);
+        let mut critters: Vec<_> = world.critters_in(center, radius).into_iter().cloned().collect();
+        critters.sort_by_key(|x| x.id);
+
+        let visible: HashSet<PlayerId> = players.iter()
+            .map(|x| x.id)
+            .filter(|x| *x != id)
+            .collect();
+        let mut events: Vec<InterestEvent> = visible.difference(&self.visible)
+            .map(|x| InterestEvent::Entered(*x))
+            .chain(self.visible.difference(&visible).map(|x| InterestEvent::Left(*x)))
+            .collect();
+        // Set order is random, keep the messages stable.
+        events.sort_by_key(|event| match *event {
+            InterestEvent::Entered(id) => (0, id),
+            InterestEvent::Left(id) => (1, id),
+        });
+        self.visible = visible;
+
+        (Snapshot { seq, players, critters }, events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+    use crate::tests::{critter_at, player_at, sized_player};
+
+    #[test]
+    fn test_from_env_ignores_nonpositive() {
+        for value in ["0", "-5"] {
+            env::set_var("RUGAR_VIEW_RADIUS", value);
+            assert_eq!(InterestConfig::default(), InterestConfig::from_env());
+        }
+        env::set_var("RUGAR_VIEW_RADIUS", "300");
+        assert_eq!(300.0, InterestConfig::from_env().view_radius);
+        env::remove_var("RUGAR_VIEW_RADIUS");
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut world = GameWorld::new();
+        world.add_player(player_at(1, Pos::new(100.0, 100.0)));
+        world.add_player(player_at(2, Pos::new(150.0, 100.0)));
+        world.add_player(player_at(3, Pos::new(600.0, 100.0)));
+        // Reaching into the view by just its edge.
+        world.add_player(sized_player(4, Pos::new(160.0, 180.0), 0.0));
+        world.update_world(vec![
+            critter_at(1, Pos::new(195.0, 100.0), 10),
+            critter_at(2, Pos::new(300.0, 100.0), 10),
+            critter_at(3, Pos::new(100.0, 210.0), 10),
+        ]);
+        let (visible, _) = Interest::new().filter(&world, 3, 1, 100.0);
+        assert_eq!(3, visible.seq);
+        assert_eq!(vec![1, 2, 4], visible.players.iter().map(|x| x.id).collect::<Vec<_>>());
+        assert_eq!(vec![1, 3], visible.critters.iter().map(|x| x.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_events() {
+        let mut interest = Interest::new();
+        let mut world = GameWorld::new();
+        for (id, x) in [(1, 100.0), (2, 150.0), (3, 600.0)] {
+            world.add_player(player_at(id, Pos::new(x, 100.0)));
+        }
+        let (_, events) = interest.filter(&world, 1, 1, 100.0);
+        assert_eq!(vec![InterestEvent::Entered(2)], events);
+
+        world.players[1].pos = Pos::new(400.0, 100.0);
+        world.players[2].pos = Pos::new(120.0, 100.0);
+        world.reindex();
+        let (_, events) = interest.filter(&world, 2, 1, 100.0);
+        assert_eq!(vec![InterestEvent::Entered(3), InterestEvent::Left(2)], events);
+
+        let (_, events) = interest.filter(&world, 3, 1, 100.0);
+        assert!(events.is_empty());
+    }
+}
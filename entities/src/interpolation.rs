@@ -0,0 +1,97 @@
+use crate::Pos;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Remote players are drawn this far in the past so there is (almost) always a
+// snapshot on both sides of the render time to interpolate between.
+pub const INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+// Snapshots older than this are of no use for interpolation anymore.
+const HISTORY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct PositionBuffer {
+    snapshots: VecDeque<(Instant, Pos)>,
+}
+
+impl PositionBuffer {
+    pub fn new() -> PositionBuffer {
+        PositionBuffer { snapshots: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, time: Instant, pos: Pos) {
+        self.snapshots.push_back((time, pos));
+        while let Some(&(oldest, _)) = self.snapshots.front() {
+            if time.duration_since(oldest) <= HISTORY {
+                break;
+            }
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Position at `time`, linearly interpolated between the two snapshots
+    // around it. Outside of the buffered range the closest snapshot is used.
+    pub fn sample(&self, time: Instant) -> Option<Pos> {
+        let after = self.snapshots.iter().position(|&(t, _)| t >= time);
+        match after {
+            None => self.snapshots.back().map(|&(_, pos)| pos),
+            Some(0) => self.snapshots.front().map(|&(_, pos)| pos),
+            Some(index) => {
+                let (t0, p0) = self.snapshots[index - 1];
+                let (t1, p1) = self.snapshots[index];
+                let span = t1.duration_since(t0).as_secs_f32();
+                let alpha = if span > 0.0 {
+                    time.duration_since(t0).as_secs_f32() / span
+                } else {
+                    1.0
+                };
+                Some(Pos::new(
+                    p0.x() + (p1.x() - p0.x()) * alpha,
+                    p0.y() + (p1.y() - p0.y()) * alpha,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_empty() {
+        let buffer = PositionBuffer::new();
+        assert!(buffer.sample(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_sample_interpolates() {
+        let start = Instant::now();
+        let mut buffer = PositionBuffer::new();
+        buffer.push(start, Pos::new(0.0, 0.0));
+        buffer.push(start + Duration::from_millis(100), Pos::new(10.0, 20.0));
+
+        let pos = buffer.sample(start + Duration::from_millis(50)).unwrap();
+        assert_eq!(5.0, pos.x());
+        assert_eq!(10.0, pos.y());
+    }
+
+    #[test]
+    fn test_sample_clamps_to_range() {
+        let start = Instant::now() + Duration::from_secs(1);
+        let mut buffer = PositionBuffer::new();
+        buffer.push(start, Pos::new(1.0, 1.0));
+        buffer.push(start + Duration::from_millis(100), Pos::new(2.0, 2.0));
+
+        assert_eq!(1.0, buffer.sample(start - Duration::from_millis(10)).unwrap().x());
+        assert_eq!(2.0, buffer.sample(start + Duration::from_secs(1)).unwrap().x());
+    }
+
+    #[test]
+    fn test_push_drops_old_snapshots() {
+        let start = Instant::now();
+        let mut buffer = PositionBuffer::new();
+        buffer.push(start, Pos::new(1.0, 1.0));
+        buffer.push(start + Duration::from_secs(2), Pos::new(2.0, 2.0));
+        assert_eq!(1, buffer.snapshots.len());
+    }
+}
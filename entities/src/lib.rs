@@ -1,60 +1,153 @@
-use rand;
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 use serde::{Serialize, Deserialize};
 use std::f32;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+
+pub mod behaviour;
+pub mod codec;
+pub mod collision;
+pub mod frame;
+pub mod handshake;
+pub mod heartbeat;
+pub mod input;
+pub mod interest;
+pub mod interpolation;
+pub mod outbound;
+pub mod snapshot;
+pub mod spawner;
+pub mod spatial;
+pub mod world;
+
+use behaviour::Behaviour;
+use handshake::{Hello, Welcome};
+use interest::InterestEvent;
+use interpolation::PositionBuffer;
+use snapshot::{Snapshot, SnapshotDelta};
+use spatial::SpatialGrid;
+use world::WorldConfig;
+
+pub const TICK_RATE: u32 = 30;
+// Seconds simulated by one tick, and by one input command.
+pub const TICK_DT: f32 = 1.0 / TICK_RATE as f32;
+// Top speed of a player at START_SIZE in pixels per second, bigger players
+// are slower. See `Player::max_speed`.
+pub const MAX_SPEED: f32 = 120.0;
+// However big a player gets, it never gets slower than this.
+pub const MIN_SPEED: f32 = 30.0;
+// How quickly players speed up and slow down, in pixels per second squared.
+pub const ACCELERATION: f32 = 600.0;
+// Size players start at, and start over at after being eaten.
+pub const START_SIZE: f32 = 10.0;
+// How much bigger than a critter or another player one has to be to eat it.
+pub const EAT_RATIO: f32 = 1.25;
+// How much of its radius the smaller one has to be inside the bigger one.
+pub const EAT_OVERLAP: f32 = 0.5;
+// How far from other players a player starts out, if there is room.
+pub const PLAYER_CLEARANCE: f32 = 100.0;
+// Random spots tried by `GameWorld::player_spawn_pos` before settling for
+// any.
+const SPAWN_ATTEMPTS: usize = 10;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Message {
     pub mtype: MessageType,
-    pub world: Option<Vec<Critter>>,
-    pub player: Option<Player>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum MessageType {
-    PlayerPosition,
-    WorldState,
+    Input(InputCommand),
+    Hello(Hello),
+    Welcome(Welcome),
+    Rejected(String),
+    PlayerLeft(PlayerId),
+    Ping(u64),
+    Pong(u64),
+    Snapshot(SnapshotDelta),
+    // The newest snapshot the client has, to make the next delta against.
+    SnapshotAck(u32),
+    Interest(InterestEvent),
+}
+
+// The keys a client held during one of its ticks. `tick` increases by one for
+// every command sent so the server can drop duplicates and report back the
+// last one it applied.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct InputCommand {
+    pub tick: u32,
+    pub moving: (Option<LRDir>, Option<UDDir>),
 }
 
 impl Message {
-    pub fn player_update(p: &Player) -> Message {
-        Message {
-            mtype: MessageType::PlayerPosition,
-            world: None,
-            player: Some(Player::copy(p)),
-        }
+    pub fn input(command: InputCommand) -> Message {
+        Message::new(MessageType::Input(command))
     }
-    pub fn world_update(w: &GameWorld) -> Message {
-        Message {
-            mtype: MessageType::WorldState,
-            world: Some(w.objects.to_vec()),
-            player: None,
-        }
+    pub fn hello(hello: Hello) -> Message {
+        Message::new(MessageType::Hello(hello))
+    }
+    pub fn welcome(welcome: Welcome) -> Message {
+        Message::new(MessageType::Welcome(welcome))
+    }
+    pub fn rejected(reason: &str) -> Message {
+        Message::new(MessageType::Rejected(reason.to_string()))
+    }
+    pub fn player_left(id: PlayerId) -> Message {
+        Message::new(MessageType::PlayerLeft(id))
+    }
+    pub fn ping(stamp: u64) -> Message {
+        Message::new(MessageType::Ping(stamp))
+    }
+    pub fn pong(stamp: u64) -> Message {
+        Message::new(MessageType::Pong(stamp))
+    }
+    pub fn snapshot(delta: SnapshotDelta) -> Message {
+        Message::new(MessageType::Snapshot(delta))
+    }
+    pub fn snapshot_ack(seq: u32) -> Message {
+        Message::new(MessageType::SnapshotAck(seq))
+    }
+    pub fn interest(event: InterestEvent) -> Message {
+        Message::new(MessageType::Interest(event))
+    }
+    fn new(mtype: MessageType) -> Message {
+        Message { mtype }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GameWorld {
+    #[serde(default)]
+    pub config: WorldConfig,
     pub players: Vec<Player>,
     pub main_player: Player,
-    pub objects: Vec<Critter>
+    pub objects: Vec<Critter>,
+    // Recent positions of the other players.
+    #[serde(skip)]
+    pub history: HashMap<PlayerId, PositionBuffer>,
+    // Where everything is, by index into `objects` and by player id. Call
+    // `reindex` after changing `objects` or `players` directly.
+    #[serde(skip)]
+    pub critter_grid: SpatialGrid<usize>,
+    #[serde(skip)]
+    pub player_grid: SpatialGrid<PlayerId>,
 }
 
 impl GameWorld {
     pub fn new() -> GameWorld {
-        let mut critters = vec![];
-        let mut rng = rand::thread_rng();
-        for _ in 0..10 {
-            let x = rng.gen_range(0.0, 800.0);
-            let y = rng.gen_range(0.0, 800.0);
-            let size = 10 * rng.gen_range(1, 10);
-            critters.push(Critter { pos_x: x, pos_y: y, size, color: random_color() })
-        }
+        GameWorld::with_config(WorldConfig::default())
+    }
+
+    // Critters are added by the server's spawner.
+    pub fn with_config(config: WorldConfig) -> GameWorld {
         GameWorld {
+            config,
             players: vec![],
             main_player: Player::new(),
-            objects: critters
+            objects: vec![],
+            history: HashMap::new(),
+            critter_grid: SpatialGrid::default(),
+            player_grid: SpatialGrid::default(),
         }
     }
 
@@ -63,8 +156,17 @@ impl GameWorld {
     }
 
     pub fn update_player(&mut self, player: Player) {
-        if !self.main_player.same_player(&player) {
-            if let Some(index) = self.players.iter().position(|x| x.name == player.name) {
+        if self.main_player.same_player(&player) {
+            self.main_player.pos = player.pos;
+            self.main_player.size = player.size;
+            self.main_player.vel_x = player.vel_x;
+            self.main_player.vel_y = player.vel_y;
+        } else {
+            self.history.entry(player.id)
+                .or_default()
+                .push(Instant::now(), player.pos);
+            self.player_grid.insert(player.id, player.pos, player.size);
+            if let Some(index) = self.players.iter().position(|x| x.id == player.id) {
                 self.players[index] = player;
             } else {
                 self.players.push(player);
@@ -72,8 +174,349 @@ impl GameWorld {
         }
     }
 
+    pub fn add_player(&mut self, player: Player) {
+        self.player_grid.insert(player.id, player.pos, player.size);
+        self.players.push(player);
+    }
+
+    pub fn remove_player(&mut self, id: PlayerId) {
+        self.players.retain(|x| x.id != id);
+        self.history.remove(&id);
+        self.player_grid.remove(id);
+    }
+
+    // Forget all other players, the main one stays.
+    pub fn clear_players(&mut self) {
+        self.players.clear();
+        self.history.clear();
+        self.player_grid.clear();
+    }
+
+    pub fn reindex(&mut self) {
+        self.critter_grid.clear();
+        for (index, critter) in self.objects.iter().enumerate() {
+            self.critter_grid.insert(index, Pos::new(critter.pos_x, critter.pos_y), critter.size as f32);
+        }
+        self.player_grid.clear();
+        for player in self.players.iter() {
+            self.player_grid.insert(player.id, player.pos, player.size);
+        }
+    }
+
+    // Critters overlapping the circle.
+    pub fn critters_in(&self, center: Pos, radius: f32) -> Vec<&Critter> {
+        self.critter_grid.query_circle(center, radius)
+            .into_iter()
+            .filter_map(|index| self.objects.get(index))
+            .collect()
+    }
+
+    pub fn nearest_critter(&self, pos: Pos) -> Option<&Critter> {
+        self.critter_grid.nearest(pos).and_then(|(index, _)| self.objects.get(index))
+    }
+
+    // Other players overlapping the circle.
+    pub fn players_in(&self, center: Pos, radius: f32) -> Vec<&Player> {
+        self.player_grid.query_circle(center, radius)
+            .into_iter()
+            .filter_map(|id| self.players.iter().find(|x| x.id == id))
+            .collect()
+    }
+
+    pub fn nearest_player(&self, pos: Pos) -> Option<&Player> {
+        self.player_grid.nearest(pos)
+            .and_then(|(id, _)| self.players.iter().find(|x| x.id == id))
+    }
+
+    // Whether something of `size` could appear at `pos` without landing on a
+    // critter or within `clearance` of a player.
+    pub fn is_free(&self, pos: Pos, size: f32, clearance: f32) -> bool {
+        self.players_in(pos, clearance + size).is_empty() && self.critters_in(pos, size).is_empty()
+    }
+
+    // Where a new or eaten player starts out. Somewhere free if that is
+    // found quickly, anywhere in the world otherwise.
+    pub fn player_spawn_pos(&self) -> Pos {
+        let random = || self.config.clamp(self.config.random_pos(), START_SIZE);
+        (0..SPAWN_ATTEMPTS)
+            .map(|_| random())
+            .find(|pos| self.is_free(*pos, START_SIZE, PLAYER_CLEARANCE))
+            .unwrap_or_else(random)
+    }
+
+    // The only critters a player can bump into during its next step. Those it
+    // can eat don't get in the way. Sorted by id, the client's grid is not in
+    // the same order as the server's and collisions depend on it.
+    fn critters_near(&self, player: &Player) -> Vec<Critter> {
+        let reach = player.size + 2.0 * MAX_SPEED * TICK_DT;
+        let mut critters: Vec<Critter> = self.critters_in(player.pos, reach)
+            .into_iter()
+            .filter(|critter| !player.can_eat(critter.size as f32))
+            .cloned()
+            .collect();
+        critters.sort_by_key(|critter| critter.id);
+        critters
+    }
+
+    // Push overlapping players apart, bigger ones pushing smaller ones out of
+    // the way. Those that could eat one another are left to `eat`. Goes by
+    // id so the outcome doesn't depend on the order of `players`.
+    pub fn separate_players(&mut self) {
+        let mut ids: Vec<PlayerId> = self.players.iter().map(|x| x.id).collect();
+        ids.sort();
+        for id in ids {
+            let index = match self.players.iter().position(|x| x.id == id) {
+                Some(index) => index,
+                None => continue,
+            };
+            let (pos, size) = (self.players[index].pos, self.players[index].size);
+            let mut others: Vec<PlayerId> = self.player_grid.query_circle(pos, size)
+                .into_iter()
+                .filter(|other| *other > id)
+                .collect();
+            others.sort();
+            for other in others {
+                let other_index = match self.players.iter().position(|x| x.id == other) {
+                    Some(other_index) => other_index,
+                    None => continue,
+                };
+                let (a, b) = (&self.players[index], &self.players[other_index]);
+                if a.can_eat(b.size) || b.can_eat(a.size) {
+                    continue;
+                }
+                let pushed = collision::push_apart((a.pos, a.size, a.mass()), (b.pos, b.size, b.mass()));
+                if let Some((a_pos, b_pos)) = pushed {
+                    self.players[index].pos = a_pos;
+                    self.players[other_index].pos = b_pos;
+                    self.config.keep_inside(&mut self.players[index]);
+                    self.config.keep_inside(&mut self.players[other_index]);
+                    self.player_grid.insert(id, self.players[index].pos, size);
+                    self.player_grid.insert(other, self.players[other_index].pos, self.players[other_index].size);
+                }
+            }
+        }
+    }
+
+    // Let every player eat whatever it covers enough of, biggest players
+    // first. Eaten players start over somewhere else.
+    pub fn eat(&mut self) -> Vec<(PlayerId, Meal)> {
+        let mut order: Vec<usize> = (0..self.players.len()).collect();
+        order.sort_by(|a, b| {
+            let (a, b) = (&self.players[*a], &self.players[*b]);
+            b.size.partial_cmp(&a.size).unwrap().then(a.id.cmp(&b.id))
+        });
+
+        let mut meals = vec![];
+        let mut eaten_critters = HashSet::new();
+        let mut eaten_players = HashSet::new();
+        for index in order {
+            let eater = self.players[index].clone();
+            if eaten_players.contains(&eater.id) {
+                continue;
+            }
+            let mut size = eater.size;
+            for critter_index in self.critter_grid.query_circle(eater.pos, eater.size) {
+                let critter = match self.objects.get(critter_index) {
+                    Some(critter) => critter,
+                    None => continue,
+                };
+                let (pos, critter_size) = (Pos::new(critter.pos_x, critter.pos_y), critter.size as f32);
+                if !eaten_critters.contains(&critter.id) && eater.can_eat(critter_size) && eater.covers(pos, critter_size) {
+                    eaten_critters.insert(critter.id);
+                    size = grown(size, critter_size);
+                    meals.push((eater.id, Meal::Critter(critter.id)));
+                }
+            }
+            for other in self.player_grid.query_circle(eater.pos, eater.size) {
+                let prey = match self.players.iter().find(|x| x.id == other) {
+                    Some(prey) => prey,
+                    None => continue,
+                };
+                if prey.id != eater.id && !eaten_players.contains(&prey.id)
+                    && eater.can_eat(prey.size) && eater.covers(prey.pos, prey.size)
+                {
+                    eaten_players.insert(prey.id);
+                    size = grown(size, prey.size);
+                    meals.push((eater.id, Meal::Player(prey.id)));
+                }
+            }
+            self.players[index].size = size;
+            // Growing may reach over the edge.
+            self.config.keep_inside(&mut self.players[index]);
+        }
+
+        if !eaten_critters.is_empty() {
+            self.objects.retain(|critter| !eaten_critters.contains(&critter.id));
+        }
+        if !meals.is_empty() {
+            self.reindex();
+        }
+        // Only once the grids are up to date, to know where is free.
+        for id in eaten_players {
+            let pos = self.player_spawn_pos();
+            if let Some(player) = self.players.iter_mut().find(|x| x.id == id) {
+                player.respawn(pos);
+                self.player_grid.insert(id, pos, START_SIZE);
+            }
+        }
+        meals
+    }
+
+    // Where to draw another player at `time`, see `PositionBuffer::sample`.
+    pub fn interpolated_pos(&self, player: &Player, time: Instant) -> Pos {
+        self.history.get(&player.id)
+            .and_then(|buffer| buffer.sample(time))
+            .unwrap_or(player.pos)
+    }
+
     pub fn update_world(&mut self, objects: Vec<Critter>) {
         self.objects = objects;
+        self.reindex();
+    }
+
+    pub fn add_critters(&mut self, critters: Vec<Critter>) {
+        for critter in critters {
+            let pos = Pos::new(critter.pos_x, critter.pos_y);
+            self.critter_grid.insert(self.objects.len(), pos, critter.size as f32);
+            self.objects.push(critter);
+        }
+    }
+
+    // Let every critter react to the closest player and move it by its
+    // velocity. Critters don't walk into players they would block.
+    pub fn move_critters(&mut self) {
+        for index in 0..self.objects.len() {
+            let critter = &self.objects[index];
+            let pos = Pos::new(critter.pos_x, critter.pos_y);
+            // Players further away go unnoticed, and looking only this far
+            // keeps it cheap with many critters in a big world. Critters
+            // are smaller than that, anyone in their way is in here too.
+            let near = self.players_in(pos, behaviour::SENSE_RADIUS);
+            let nearest = near.iter()
+                .min_by(|a, b| {
+                    let (mut a, mut b) = (a.pos, b.pos);
+                    a.object_distance_2(pos).partial_cmp(&b.object_distance_2(pos)).unwrap()
+                })
+                .copied();
+            let (vel_x, vel_y) = behaviour::steer(critter, nearest);
+            let size = critter.size as f32;
+            let next = self.config.clamp(Pos::new(pos.x() + vel_x * TICK_DT, pos.y() + vel_y * TICK_DT), size);
+            if next == pos {
+                let critter = &mut self.objects[index];
+                critter.vel_x = 0.0;
+                critter.vel_y = 0.0;
+                continue;
+            }
+            let blocked = near.iter().any(|player| {
+                !player.can_eat(size)
+                    && collision::contact(next, size, player.pos, player.size).is_some()
+                    && collision::contact(pos, size, player.pos, player.size).is_none()
+            });
+
+            let critter = &mut self.objects[index];
+            if blocked {
+                critter.vel_x = 0.0;
+                critter.vel_y = 0.0;
+                continue;
+            }
+            critter.vel_x = vel_x;
+            critter.vel_y = vel_y;
+            critter.pos_x = next.x();
+            critter.pos_y = next.y();
+            self.critter_grid.insert(index, next, size);
+        }
+    }
+
+    pub fn snapshot(&self, seq: u32) -> Snapshot {
+        Snapshot {
+            seq,
+            players: self.players.iter().map(Player::copy).collect(),
+            critters: self.objects.to_vec(),
+        }
+    }
+
+    // Take over the server's view of the world. Players missing from the
+    // snapshot are gone, the main player is reconciled like for a single
+    // update.
+    pub fn apply_snapshot(&mut self, snapshot: &Snapshot, pending: &mut VecDeque<InputCommand>) {
+        for player in snapshot.players.iter() {
+            if self.main_player.same_player(player) {
+                self.reconcile(Player::copy(player), pending);
+            } else {
+                self.update_player(Player::copy(player));
+            }
+        }
+        let gone: Vec<PlayerId> = self.players.iter()
+            .map(|x| x.id)
+            .filter(|id| !snapshot.players.iter().any(|x| x.id == *id))
+            .collect();
+        for id in gone {
+            self.remove_player(id);
+        }
+        // Rebuilding the grid is not free. Unless critters were eaten,
+        // spawned or came into view, they are the same ones in the same
+        // order and only those that moved need to be filed again.
+        let same_critters = self.objects.len() == snapshot.critters.len()
+            && self.objects.iter().zip(snapshot.critters.iter()).all(|(a, b)| a.id == b.id);
+        if !same_critters {
+            self.update_world(snapshot.critters.to_vec());
+            return;
+        }
+        for (index, critter) in snapshot.critters.iter().enumerate() {
+            if self.objects[index] != *critter {
+                self.critter_grid.insert(index, Pos::new(critter.pos_x, critter.pos_y), critter.size as f32);
+                self.objects[index] = critter.clone();
+            }
+        }
+    }
+
+    // Move a player by one step as described by the command. Commands that
+    // are not newer than the last one applied are ignored.
+    pub fn apply_input(&mut self, id: PlayerId, command: &InputCommand) -> bool {
+        let index = match self.players.iter().position(|x| x.id == id) {
+            Some(index) if command.tick > self.players[index].last_input => index,
+            _ => return false,
+        };
+        let critters = self.critters_near(&self.players[index]);
+        let player = &mut self.players[index];
+        player.last_input = command.tick;
+        player.moving = command.moving;
+        player.step(&critters, TICK_DT);
+        self.config.keep_inside(player);
+        self.player_grid.insert(id, player.pos, player.size);
+        true
+    }
+
+    // Client side prediction: move the main player right away instead of
+    // waiting for the server to echo the command back.
+    pub fn predict(&mut self, command: &InputCommand) {
+        let moving = self.main_player.moving;
+        self.main_player.moving = command.moving;
+        let critters = self.critters_near(&self.main_player);
+        self.main_player.step(&critters, TICK_DT);
+        self.config.keep_inside(&mut self.main_player);
+        self.main_player.moving = moving;
+    }
+
+    // Take the server's state for the main player and replay the commands it
+    // has not applied yet on top of it. Acknowledged commands are dropped.
+    pub fn reconcile(&mut self, player: Player, pending: &mut VecDeque<InputCommand>) {
+        while let Some(command) = pending.front() {
+            if command.tick > player.last_input {
+                break;
+            }
+            pending.pop_front();
+        }
+        self.update_player(player);
+        for command in pending.iter() {
+            self.predict(command);
+        }
+    }
+}
+
+impl Default for GameWorld {
+    fn default() -> Self {
+        GameWorld::new()
     }
 }
 
@@ -89,12 +532,36 @@ pub enum UDDir {
     Down = 1,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+pub type CritterId = u32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Critter {
+    pub id: CritterId,
     pub pos_x: f32,
     pub pos_y: f32,
     pub size: u32,
     pub color: (f32, f32, f32, f32),
+    // In pixels per second, see `GameWorld::move_critters`.
+    pub vel_x: f32,
+    pub vel_y: f32,
+    pub behaviour: Behaviour,
+    // Where it was spawned, wandering keeps close to it. Only the server
+    // needs to know.
+    #[serde(skip)]
+    pub home: Pos,
+}
+
+// What a player eats, see `GameWorld::eat`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Meal {
+    Critter(CritterId),
+    Player(PlayerId),
+}
+
+// Eating keeps the total area, so a player slows down in growing as it gets
+// bigger.
+pub fn grown(size: f32, eaten: f32) -> f32 {
+    (size * size + eaten * eaten).sqrt()
 }
 
 pub fn random_color() -> (f32, f32, f32, f32) {
@@ -105,16 +572,25 @@ pub fn random_color() -> (f32, f32, f32, f32) {
     1.0)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+// Assigned by the server when a player joins, 0 until then.
+pub type PlayerId = u32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Player {
+    pub id: PlayerId,
+    // Display name, not necessarily unique.
     pub name: String,
     pub pos: Pos,
-    pub size: u32,
+    pub size: f32,
+    // In pixels per second.
+    pub vel_x: f32,
+    pub vel_y: f32,
     pub moving: (Option<LRDir>, Option<UDDir>),
     pub prev_move: (Option<LRDir>, Option<UDDir>),
+    pub last_input: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
 pub struct Pos {
     pos_x: f32,
     pos_y: f32,
@@ -165,7 +641,7 @@ impl Player {
     }
 
     pub fn same_player(&self, p: &Player) -> bool {
-        self.name == p.name
+        self.id == p.id
     }
 
     pub fn set_name(&mut self, name: &str) {
@@ -176,18 +652,112 @@ impl Player {
     // but only second part because we want to capture player inside critter
     pub fn intersect(&mut self, pos: Pos, size: u32) -> bool {
         let distance = self.pos.object_distance_2(pos);
-        let high_r: f32 = ((self.size + size) * (self.size + size)) as f32;
+        let high_r: f32 = (self.size + size as f32) * (self.size + size as f32);
         distance <= high_r
     }
 
-    pub fn save_prev_move(&mut self) -> () {
-        match self.moving.0 {
-            Some(_) => self.prev_move = self.moving,
-            None => match self.moving.1 {
-                Some(_) => self.prev_move = self.moving,
-                _ => ()
+    // Goes with the area, like growing does.
+    pub fn mass(&self) -> f32 {
+        self.size * self.size
+    }
+
+    pub fn can_eat(&self, size: f32) -> bool {
+        self.size >= size * EAT_RATIO
+    }
+
+    // Whether something of `size` at `pos` is far enough inside this player
+    // to be eaten, see EAT_OVERLAP.
+    pub fn covers(&self, pos: Pos, size: f32) -> bool {
+        let reach = self.size - size * EAT_OVERLAP;
+        let (dx, dy) = (pos.x() - self.pos.x(), pos.y() - self.pos.y());
+        reach > 0.0 && dx * dx + dy * dy <= reach * reach
+    }
+
+    // Start over at `pos` after being eaten, without any of the speed or
+    // direction from before.
+    pub fn respawn(&mut self, pos: Pos) {
+        self.pos = pos;
+        self.size = START_SIZE;
+        self.vel_x = 0.0;
+        self.vel_y = 0.0;
+        self.moving = (None, None);
+        self.prev_move = (None, None);
+    }
+
+    pub fn save_prev_move(&mut self) {
+        if self.moving.0.is_some() || self.moving.1.is_some() {
+            self.prev_move = self.moving;
+        }
+    }
+
+    pub fn direction(&self) -> (f32, f32) {
+        let mut x: f32 = 0.0;
+        let mut y: f32 = 0.0;
+        if let Some(xx) = self.moving.0 {
+            x = (xx as i32) as f32;
+        }
+        if let Some(yy) = self.moving.1 {
+            y = (yy as i32) as f32;
+        }
+        (x, y)
+    }
+
+    // Growing by area costs speed, with the square root of the size.
+    pub fn max_speed(&self) -> f32 {
+        (MAX_SPEED * (START_SIZE / self.size.max(START_SIZE)).sqrt()).max(MIN_SPEED)
+    }
+
+    pub fn at_rest(&self) -> bool {
+        self.vel_x == 0.0 && self.vel_y == 0.0
+    }
+
+    // Change the velocity by at most ACCELERATION towards top speed in the
+    // held direction, or towards a stop if nothing is held. Diagonals are
+    // no faster than straight lines.
+    pub fn accelerate(&mut self, dt: f32) {
+        let (x, y) = self.direction();
+        let length = (x * x + y * y).sqrt();
+        let (target_x, target_y) = if length == 0.0 {
+            (0.0, 0.0)
+        } else {
+            (x / length * self.max_speed(), y / length * self.max_speed())
+        };
+        let (dx, dy) = (target_x - self.vel_x, target_y - self.vel_y);
+        let change = (dx * dx + dy * dy).sqrt();
+        let limit = ACCELERATION * dt;
+        if change <= limit {
+            self.vel_x = target_x;
+            self.vel_y = target_y;
+        } else {
+            self.vel_x += dx / change * limit;
+            self.vel_y += dy / change * limit;
+        }
+    }
+
+    // Accelerate, then move by the velocity over `dt` seconds. Critters in
+    // the way push the player back out, whatever part of the velocity goes
+    // into them is lost and the rest slides along their edge.
+    // Returns whether the player moved.
+    pub fn step(&mut self, critters: &[Critter], dt: f32) -> bool {
+        self.accelerate(dt);
+        if self.at_rest() {
+            return false;
+        }
+        let start = self.pos;
+        self.pos.move_player(self.vel_x * dt, self.vel_y * dt);
+
+        let obstacles: Vec<(Pos, f32)> = critters.iter()
+            .map(|critter| (Pos::new(critter.pos_x, critter.pos_y), critter.size as f32))
+            .collect();
+        for (other, radius) in obstacles.iter() {
+            if let Some(contact) = collision::contact(self.pos, self.size, *other, *radius) {
+                let (vel_x, vel_y) = collision::slide((self.vel_x, self.vel_y), contact.normal);
+                self.vel_x = vel_x;
+                self.vel_y = vel_y;
             }
         }
+        self.pos = collision::resolve(self.pos, self.size, &obstacles);
+        self.pos != start
     }
 
     pub fn opposite_direction(&mut self) -> bool {
@@ -215,21 +785,29 @@ impl Player {
 
     pub fn new() -> Player {
         Player {
+            id: 0,
             name: Player::random_username(),
             pos: Pos { pos_x: 0.0, pos_y: 0.0 },
-            size: 10,
+            size: START_SIZE,
+            vel_x: 0.0,
+            vel_y: 0.0,
             moving: (None, None),
             prev_move: (None, None),
+            last_input: 0,
         }
     }
 
     pub fn copy(p: &Player) -> Player {
         Player {
+            id: p.id,
             name: String::from(&p.name),
             pos: Pos { pos_x: p.pos.pos_x, pos_y: p.pos.pos_y },
             size: p.size,
-            moving: (None, None),
+            vel_x: p.vel_x,
+            vel_y: p.vel_y,
+            moving: p.moving,
             prev_move: (None, None),
+            last_input: p.last_input,
         }
     }
 }
@@ -238,6 +816,22 @@ impl Player {
 mod tests {
     use super::*;
 
+    // Fixtures shared by the tests of every module.
+    pub(crate) fn player_at(id: PlayerId, pos: Pos) -> Player {
+        let mut player = Player::new();
+        player.id = id;
+        player.pos = pos;
+        player
+    }
+
+    pub(crate) fn sized_player(id: PlayerId, pos: Pos, size: f32) -> Player {
+        Player { size, ..player_at(id, pos) }
+    }
+
+    pub(crate) fn critter_at(id: CritterId, pos: Pos, size: u32) -> Critter {
+        Critter { id, pos_x: pos.x(), pos_y: pos.y(), size, color: (1.0, 1.0, 1.0, 1.0), ..Critter::default() }
+    }
+
     #[test]
     fn test_dummy() {
         let mut obj = Pos { pos_x: 10.0, pos_y: 10.0 };
@@ -253,12 +847,357 @@ mod tests {
     #[test]
     fn test_intersect_dummy() {
         let mut p = Player::new();
-        assert_eq!(true, p.intersect(Pos { pos_x: 0.0, pos_y: 0.0 }, 1));
+        assert!(p.intersect(Pos { pos_x: 0.0, pos_y: 0.0 }, 1));
     }
 
     #[test]
     fn test_intersect_sized() {
         let mut p = Player::new();
-        assert_eq!(true, p.intersect(Pos { pos_x: 50.0, pos_y: 50.0 }, 100));
+        assert!(p.intersect(Pos { pos_x: 50.0, pos_y: 50.0 }, 100));
+    }
+
+    #[test]
+    fn test_step_blocked_by_critter() {
+        let mut p = Player::new();
+        p.moving = (Some(LRDir::Right), None);
+        let critters = vec![
+            critter_at(1, Pos::new(21.0, 0.0), 10)
+        ];
+        for _ in 0..TICK_RATE {
+            p.step(&critters, TICK_DT);
+        }
+        // Held at the edge of the critter, not getting any further.
+        assert!((p.pos.x() - 1.0).abs() < 1e-4);
+        assert_eq!(0.0, p.pos.y());
+        let x = p.pos.x();
+        p.step(&critters, TICK_DT);
+        assert!((p.pos.x() - x).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_step_slides_along_critter() {
+        let mut p = Player::new();
+        p.moving = (Some(LRDir::Right), Some(UDDir::Down));
+        let critters = vec![
+            critter_at(1, Pos::new(30.0, 0.0), 10),
+        ];
+        for _ in 0..TICK_RATE {
+            p.step(&critters, TICK_DT);
+        }
+        // Pushed down and around it rather than stopped.
+        let mut distance = p.pos;
+        assert!(distance.object_distance(Pos::new(30.0, 0.0)) >= 20.0 - 1e-3);
+        assert!(p.pos.y() > 20.0);
+        assert!(p.vel_y > 0.0);
+    }
+
+    #[test]
+    fn test_step_out_of_critter() {
+        let mut p = Player::new();
+        p.moving = (Some(LRDir::Left), None);
+        let critters = vec![
+            critter_at(1, Pos::new(5.0, 0.0), 10),
+        ];
+        assert!(p.step(&critters, TICK_DT));
+        assert_eq!(Pos::new(-15.0, 0.0), p.pos);
+    }
+
+    #[test]
+    fn test_max_speed() {
+        let mut p = Player::new();
+        assert_eq!(MAX_SPEED, p.max_speed());
+        p.size = 4.0 * START_SIZE;
+        assert_eq!(MAX_SPEED / 2.0, p.max_speed());
+        p.size = 1000.0;
+        assert_eq!(MIN_SPEED, p.max_speed());
+    }
+
+    #[test]
+    fn test_accelerate() {
+        let mut p = Player::new();
+        p.moving = (Some(LRDir::Right), Some(UDDir::Down));
+        p.step(&[], TICK_DT);
+        assert!((ACCELERATION * TICK_DT * TICK_DT - (p.pos.x().powi(2) + p.pos.y().powi(2)).sqrt()).abs() < 1e-5);
+        // Diagonals top out at the same speed.
+        for _ in 0..TICK_RATE {
+            p.step(&[], TICK_DT);
+        }
+        assert!(((p.vel_x.powi(2) + p.vel_y.powi(2)).sqrt() - MAX_SPEED).abs() < 1e-3);
+        assert!((p.vel_x - p.vel_y).abs() < 1e-3);
+
+        // Letting go slows down to a stop, still moving meanwhile.
+        p.moving = (None, None);
+        let x = p.pos.x();
+        assert!(p.step(&[], TICK_DT));
+        assert!(p.pos.x() > x);
+        for _ in 0..TICK_RATE {
+            p.step(&[], TICK_DT);
+        }
+        assert!(p.at_rest());
+        assert!(!p.step(&[], TICK_DT));
+    }
+
+    #[test]
+    fn test_apply_input_ignores_old_commands() {
+        let mut world = GameWorld::new();
+        world.add_player(player_at(1, Pos::new(100.0, 100.0)));
+
+        let command = InputCommand { tick: 1, moving: (None, Some(UDDir::Down)) };
+        assert!(world.apply_input(1, &command));
+        assert!(!world.apply_input(1, &command));
+        assert!(!world.apply_input(2, &command));
+        assert_eq!(100.0 + ACCELERATION * TICK_DT * TICK_DT, world.players[0].pos.y());
+        assert_eq!(1, world.players[0].last_input);
+    }
+
+    #[test]
+    fn test_remove_player() {
+        let mut world = GameWorld::new();
+        for id in 1..=2 {
+            world.update_player(player_at(id, Pos::default()));
+        }
+        world.remove_player(1);
+        assert_eq!(1, world.players.len());
+        assert_eq!(2, world.players[0].id);
+        assert!(!world.history.contains_key(&1));
+    }
+
+    #[test]
+    fn test_reconcile_replays_pending_inputs() {
+        let mut world = GameWorld::new();
+        world.objects = vec![];
+        world.main_player.pos = Pos::new(100.0, 100.0);
+        let mut pending = VecDeque::new();
+        for tick in 1..=3 {
+            let command = InputCommand { tick, moving: (Some(LRDir::Right), None) };
+            world.predict(&command);
+            pending.push_back(command);
+        }
+        let first = ACCELERATION * TICK_DT * TICK_DT;
+        assert!((100.0 + 6.0 * first - world.main_player.pos.x()).abs() < 1e-4);
+        let predicted = world.main_player.pos.x();
+
+        // The server applied the first command only, and saw us start further
+        // down than we thought.
+        let mut server = Player::copy(&world.main_player);
+        server.pos = Pos::new(100.0 + first, 108.0);
+        server.vel_x = ACCELERATION * TICK_DT;
+        server.last_input = 1;
+        world.reconcile(server, &mut pending);
+
+        assert_eq!(2, pending.len());
+        assert_eq!(predicted, world.main_player.pos.x());
+        assert_eq!(108.0, world.main_player.pos.y());
+        assert_eq!((None, None), world.main_player.moving);
+    }
+
+    #[test]
+    fn test_apply_snapshot() {
+        let mut world = GameWorld::new();
+        world.main_player.id = 1;
+        world.update_player(player_at(3, Pos::default()));
+
+        let mut server = GameWorld::new();
+        for id in 1..=2 {
+            server.add_player(player_at(id, Pos::new(8.0, 0.0)));
+        }
+        world.apply_snapshot(&server.snapshot(1), &mut VecDeque::new());
+
+        assert_eq!(8.0, world.main_player.pos.x());
+        assert_eq!(vec![2], world.players.iter().map(|x| x.id).collect::<Vec<_>>());
+        assert_eq!(server.objects, world.objects);
+    }
+
+    #[test]
+    fn test_apply_snapshot_moves_critters() {
+        let mut world = GameWorld::new();
+        let mut server = GameWorld::new();
+        server.update_world(vec![
+            critter_at(1, Pos::new(100.0, 100.0), 10),
+            critter_at(2, Pos::new(300.0, 100.0), 10),
+        ]);
+        world.apply_snapshot(&server.snapshot(1), &mut VecDeque::new());
+
+        // Only moved.
+        server.objects[1].pos_x = 400.0;
+        world.apply_snapshot(&server.snapshot(2), &mut VecDeque::new());
+        assert_eq!(server.objects, world.objects);
+        assert_eq!(2, world.nearest_critter(Pos::new(390.0, 100.0)).unwrap().id);
+        assert!(world.critters_in(Pos::new(300.0, 100.0), 5.0).is_empty());
+
+        // One eaten, the rest move up in the list.
+        server.update_world(vec![server.objects[1].clone()]);
+        world.apply_snapshot(&server.snapshot(3), &mut VecDeque::new());
+        assert_eq!(server.objects, world.objects);
+        assert_eq!(2, world.nearest_critter(Pos::new(100.0, 100.0)).unwrap().id);
+    }
+
+    #[test]
+    fn test_grid_follows_players() {
+        let mut world = GameWorld::new();
+        world.update_world(vec![
+            critter_at(1, Pos::new(100.0, 0.0), 10),
+            critter_at(2, Pos::new(300.0, 0.0), 10),
+        ]);
+        world.add_player(player_at(1, Pos::default()));
+
+        let command = InputCommand { tick: 1, moving: (Some(LRDir::Right), None) };
+        world.apply_input(1, &command);
+        assert_eq!(1, world.players_in(world.players[0].pos, 0.0).len());
+        assert!(world.players_in(Pos::new(-20.0, 0.0), 1.0).is_empty());
+        assert_eq!(1, world.nearest_critter(Pos::new(0.0, 0.0)).unwrap().id);
+        assert_eq!(2, world.critters_in(Pos::new(250.0, 0.0), 45.0)[0].id);
+
+        world.remove_player(1);
+        assert!(world.nearest_player(Pos::new(0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_eat_critters() {
+        let mut world = GameWorld::new();
+        world.update_world(vec![
+            critter_at(1, Pos::new(6.0, 0.0), 6),
+            // Too far out of the player.
+            critter_at(2, Pos::new(0.0, 9.0), 6),
+            // Too big, even after eating the first one.
+            critter_at(3, Pos::new(0.0, 0.0), 10),
+        ]);
+        world.add_player(player_at(1, Pos::default()));
+
+        assert_eq!(vec![(1, Meal::Critter(1))], world.eat());
+        assert_eq!(vec![2, 3], world.objects.iter().map(|x| x.id).collect::<Vec<_>>());
+        assert_eq!(grown(START_SIZE, 6.0), world.players[0].size);
+        assert!(world.eat().is_empty());
+    }
+
+    #[test]
+    fn test_eat_players() {
+        let mut world = GameWorld::new();
+        world.update_world(vec![]);
+        for (id, x, size) in [(1, 0.0, 30.0), (2, 10.0, 20.0), (3, 26.0, 10.0)] {
+            world.add_player(sized_player(id, Pos::new(x, 0.0), size));
+        }
+
+        // 3 is not far enough inside 1, but 2 is, and 2 is gone before it
+        // gets to eat 3.
+        assert_eq!(vec![(1, Meal::Player(2))], world.eat());
+        assert_eq!(grown(30.0, 20.0), world.players[0].size);
+        assert_eq!(START_SIZE, world.players[1].size);
+        assert_eq!(10.0, world.players[2].size);
+    }
+
+    #[test]
+    fn test_eaten_players_start_over_inside_the_world() {
+        for _ in 0..20 {
+            let mut world = GameWorld::with_config(WorldConfig::new(40.0, 40.0));
+            world.add_player(sized_player(1, Pos::new(20.0, 20.0), 30.0));
+            world.add_player(player_at(2, Pos::new(20.0, 20.0)));
+            assert_eq!(vec![(1, Meal::Player(2))], world.eat());
+            let pos = world.players[1].pos;
+            assert_eq!(pos, world.config.clamp(pos, START_SIZE));
+        }
+    }
+
+    #[test]
+    fn test_eaten_players_start_over_somewhere_free() {
+        for _ in 0..20 {
+            let mut world = GameWorld::new();
+            world.update_world(vec![critter_at(1, Pos::new(150.0, 150.0), 90)]);
+            world.add_player(sized_player(1, Pos::new(400.0, 400.0), 30.0));
+            let mut prey = player_at(2, Pos::new(400.0, 400.0));
+            prey.moving = (Some(LRDir::Right), Some(UDDir::Down));
+            prey.vel_x = 50.0;
+            prey.vel_y = 50.0;
+            world.add_player(prey);
+            assert_eq!(vec![(1, Meal::Player(2))], world.eat());
+
+            let (eater, prey) = (&world.players[0], &world.players[1]);
+            assert_eq!((0.0, 0.0), (prey.vel_x, prey.vel_y));
+            assert_eq!((None, None), prey.moving);
+            assert_eq!(START_SIZE, prey.size);
+            assert!(world.critters_in(prey.pos, START_SIZE).is_empty());
+            let mut distance = prey.pos;
+            assert!(distance.object_distance(eater.pos) > eater.size + START_SIZE + PLAYER_CLEARANCE);
+            assert_eq!(vec![2], world.players_in(prey.pos, 0.0).iter().map(|x| x.id).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_edible_critters_dont_block() {
+        let mut world = GameWorld::new();
+        world.update_world(vec![
+            critter_at(1, Pos::new(114.0, 100.0), 4),
+        ]);
+        world.add_player(player_at(1, Pos::new(100.0, 100.0)));
+        let command = InputCommand { tick: 1, moving: (Some(LRDir::Right), None) };
+        assert!(world.apply_input(1, &command));
+        assert_eq!(100.0 + ACCELERATION * TICK_DT * TICK_DT, world.players[0].pos.x());
+    }
+
+    #[test]
+    fn test_move_critters() {
+        let mut world = GameWorld::new();
+        let chaser = |id, x| Critter { behaviour: Behaviour::Chase, ..critter_at(id, Pos::new(x, 100.0), 30) };
+        world.update_world(vec![chaser(1, 200.0), chaser(2, 58.5)]);
+        world.add_player(player_at(1, Pos::new(100.0, 100.0)));
+        world.move_critters();
+
+        assert_eq!((-behaviour::CHASE_SPEED, 0.0), (world.objects[0].vel_x, world.objects[0].vel_y));
+        assert!((200.0 - behaviour::CHASE_SPEED * TICK_DT - world.objects[0].pos_x).abs() < 1e-4);
+        // Stops short of the player instead of pinning it.
+        assert!((59.0 - world.objects[1].pos_x).abs() < 1e-4);
+        assert_eq!(vec![0], world.critter_grid.query_circle(Pos::new(198.0, 100.0), 1.0));
+    }
+
+    #[test]
+    fn test_critters_blocked_by_players() {
+        let mut world = GameWorld::new();
+        let chaser = Critter { behaviour: Behaviour::Chase, ..critter_at(1, Pos::new(200.0, 100.0), 30) };
+        world.update_world(vec![chaser.clone(), Critter { id: 2, pos_y: 300.0, ..chaser }]);
+        // Both go after a small player ahead of them, and each touches
+        // another player on the way there.
+        world.add_player(sized_player(1, Pos::new(240.0, 100.0), 5.0));
+        world.add_player(sized_player(2, Pos::new(240.0, 300.0), 5.0));
+        world.add_player(sized_player(3, Pos::new(230.0, 140.0), 20.0));
+        world.add_player(sized_player(4, Pos::new(242.0, 356.0), 40.0));
+        world.move_critters();
+
+        // Too small to eat it, so it can't push into it.
+        assert_eq!(200.0, world.objects[0].pos_x);
+        assert_eq!((0.0, 0.0), (world.objects[0].vel_x, world.objects[0].vel_y));
+        // Big enough to eat it, so it is free to walk into it.
+        assert!((200.0 + behaviour::CHASE_SPEED * TICK_DT - world.objects[1].pos_x).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_separate_players() {
+        let mut world = GameWorld::new();
+        for (id, x, size) in [(1, 100.0, 12.0), (2, 112.0, 10.0), (3, 200.0, 20.0), (4, 210.0, 10.0)] {
+            world.add_player(sized_player(id, Pos::new(x, 100.0), size));
+        }
+        world.separate_players();
+
+        // The bigger one moves less, both end up touching.
+        let (a, b) = (world.players[0].pos.x(), world.players[1].pos.x());
+        assert!((b - a - 22.0).abs() < 1e-4);
+        assert!(100.0 - a < b - 112.0);
+        assert!(100.0 - a > 0.0);
+        assert_eq!(1, world.players_in(Pos::new(b, 100.0), 0.0).len());
+        // 3 can eat 4, so they stay put.
+        assert_eq!(200.0, world.players[2].pos.x());
+        assert_eq!(210.0, world.players[3].pos.x());
+    }
+
+    #[test]
+    fn test_stays_inside_the_world() {
+        let mut world = GameWorld::with_config(WorldConfig::new(100.0, 100.0));
+        world.add_player(player_at(1, Pos::new(89.0, 50.0)));
+        for tick in 1..=10 {
+            world.apply_input(1, &InputCommand { tick, moving: (Some(LRDir::Right), Some(UDDir::Down)) });
+        }
+        // Sliding down along the right edge.
+        assert_eq!(90.0, world.players[0].pos.x());
+        assert!(world.players[0].pos.y() > 51.0);
+        assert_eq!(0.0, world.players[0].vel_x);
     }
 }
@@ -0,0 +1,141 @@
+use crate::{Message, MessageType};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Messages waiting to be written to one client. Above this the client is
+// not keeping up.
+pub const MAX_QUEUED: usize = 256;
+// How long a client may stay above `MAX_QUEUED` before it is dropped.
+pub const OVERLOAD_GRACE: Duration = Duration::from_secs(2);
+
+// Queued messages that a newer one makes pointless. Snapshot deltas are all
+// made against a snapshot the client already has, so only the newest one is
+// worth sending, and the same goes for pings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Key {
+    Snapshot,
+    Ping,
+}
+
+fn key(message: &Message) -> Option<Key> {
+    match message.mtype {
+        MessageType::Snapshot(_) => Some(Key::Snapshot),
+        MessageType::Ping(_) => Some(Key::Ping),
+        _ => None
+    }
+}
+
+// Bounded queue of messages that have not been handed to the socket yet, so
+// a slow client only ever holds up itself.
+#[derive(Debug, Default)]
+pub struct OutboundQueue {
+    messages: VecDeque<Message>,
+    // When the queue last went over `MAX_QUEUED`.
+    over_since: Option<Instant>,
+}
+
+impl OutboundQueue {
+    pub fn new() -> OutboundQueue {
+        OutboundQueue { messages: VecDeque::new(), over_since: None }
+    }
+
+    // A message replacing one still in the queue drops it, so the client
+    // gets the newest state without falling further behind. It still goes
+    // last, after everything that was sent before it.
+    pub fn push(&mut self, message: Message, now: Instant) {
+        if let Some(k) = key(&message) {
+            self.messages.retain(|queued| key(queued) != Some(k));
+        }
+        self.messages.push_back(message);
+        if self.messages.len() > MAX_QUEUED && self.over_since.is_none() {
+            self.over_since = Some(now);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Message> {
+        let message = self.messages.pop_front();
+        if self.messages.len() <= MAX_QUEUED {
+            self.over_since = None;
+        }
+        message
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Whether the client has been over the limit for too long.
+    pub fn overloaded(&self, now: Instant) -> bool {
+        match self.over_since {
+            Some(since) => now.duration_since(since) > OVERLOAD_GRACE,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlayerId;
+    use crate::interest::InterestEvent;
+    use crate::snapshot::Snapshot;
+
+    fn snapshot(seq: u32) -> Message {
+        Message::snapshot(Snapshot { seq, players: vec![], critters: vec![] }.delta(None))
+    }
+
+    fn describe(message: Message) -> String {
+        match message.mtype {
+            MessageType::Snapshot(delta) => format!("snapshot {}", delta.seq),
+            MessageType::Interest(InterestEvent::Entered(id)) => format!("entered {}", id),
+            MessageType::Interest(InterestEvent::Left(id)) => format!("left {}", id),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_coalesces_snapshots() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::new();
+        // Two ticks worth of interest events, each followed by its snapshot.
+        queue.push(Message::interest(InterestEvent::Entered(1)), now);
+        queue.push(snapshot(1), now);
+        queue.push(Message::interest(InterestEvent::Left(1)), now);
+        queue.push(Message::interest(InterestEvent::Entered(2)), now);
+        queue.push(snapshot(2), now);
+
+        // The newer snapshot still comes after the events leading up to it.
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop()).map(describe).collect();
+        assert_eq!(vec!["entered 1", "left 1", "entered 2", "snapshot 2"], order);
+    }
+
+    #[test]
+    fn test_keeps_other_messages() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::new();
+        queue.push(Message::player_left(1), now);
+        queue.push(Message::player_left(2), now);
+        queue.push(Message::pong(1), now);
+        queue.push(Message::pong(2), now);
+        assert_eq!(4, queue.len());
+    }
+
+    #[test]
+    fn test_overloaded() {
+        let now = Instant::now();
+        let mut queue = OutboundQueue::new();
+        for id in 0..=MAX_QUEUED as PlayerId {
+            queue.push(Message::player_left(id), now);
+        }
+        assert!(!queue.overloaded(now + OVERLOAD_GRACE));
+        assert!(queue.overloaded(now + OVERLOAD_GRACE + Duration::from_millis(1)));
+
+        // Catching up clears it.
+        queue.pop();
+        assert!(!queue.overloaded(now + OVERLOAD_GRACE * 2));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{Critter, CritterId, Player, PlayerId};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+// Snapshots kept around on both ends, about a second's worth. A client whose
+// last acknowledgement is older than that gets the full state again.
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+// Everything a client knows about the world at one tick.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub seq: u32,
+    pub players: Vec<Player>,
+    pub critters: Vec<Critter>,
+}
+
+// How to get from the snapshot `base` to snapshot `seq`. Without a base
+// this is the full state.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotDelta {
+    pub seq: u32,
+    pub base: Option<u32>,
+    // New or changed since the base.
+    pub players: Vec<Player>,
+    pub removed_players: Vec<PlayerId>,
+    pub critters: Vec<Critter>,
+    pub removed_critters: Vec<CritterId>,
+}
+
+impl Snapshot {
+    pub fn delta(&self, base: Option<&Snapshot>) -> SnapshotDelta {
+        let empty = Snapshot::default();
+        let old = base.unwrap_or(&empty);
+        let (players, removed_players) = diff(&self.players, &old.players, |x| x.id);
+        let (critters, removed_critters) = diff(&self.critters, &old.critters, |x| x.id);
+        SnapshotDelta {
+            seq: self.seq,
+            base: base.map(|base| base.seq),
+            players,
+            removed_players,
+            critters,
+            removed_critters,
+        }
+    }
+}
+
+impl SnapshotDelta {
+    // Rebuild the full snapshot. `base` has to be the snapshot the delta was
+    // made against, None if it can't be applied.
+    pub fn apply(self, base: Option<&Snapshot>) -> Option<Snapshot> {
+        let empty = Snapshot::default();
+        let old = match (self.base, base) {
+            (None, _) => &empty,
+            (Some(seq), Some(base)) if base.seq == seq => base,
+            _ => return None,
+        };
+        Some(Snapshot {
+            seq: self.seq,
+            players: patch(&old.players, self.players, &self.removed_players, |x| x.id),
+            critters: patch(&old.critters, self.critters, &self.removed_critters, |x| x.id),
+        })
+    }
+}
+
+// What is new or different in `new` and the ids of what is gone.
+fn diff<T, K, F>(new: &[T], old: &[T], id: F) -> (Vec<T>, Vec<K>)
+    where T: Clone + PartialEq, K: Copy + Eq + Hash, F: Fn(&T) -> K
+{
+    let old_by_id: HashMap<K, &T> = old.iter().map(|x| (id(x), x)).collect();
+    let changed = new.iter()
+        .filter(|x| old_by_id.get(&id(x)) != Some(x))
+        .cloned()
+        .collect();
+    let new_ids: HashSet<K> = new.iter().map(&id).collect();
+    let removed = old.iter()
+        .map(id)
+        .filter(|x| !new_ids.contains(x))
+        .collect();
+    (changed, removed)
+}
+
+// The inverse of `diff`. Entries keep their order, new ones go last.
+fn patch<T, K, F>(old: &[T], changed: Vec<T>, removed: &[K], id: F) -> Vec<T>
+    where T: Clone, K: Copy + Eq + Hash, F: Fn(&T) -> K
+{
+    let removed: HashSet<K> = removed.iter().copied().collect();
+    let mut result: Vec<T> = old.iter()
+        .filter(|x| !removed.contains(&id(x)))
+        .cloned()
+        .collect();
+    let index: HashMap<K, usize> = result.iter().enumerate().map(|(i, x)| (id(x), i)).collect();
+    for x in changed {
+        match index.get(&id(&x)) {
+            Some(&i) => result[i] = x,
+            None => result.push(x),
+        }
+    }
+    result
+}
+
+// The last few snapshots sent to (or received from) the other side, and the
+// newest one it confirmed having.
+#[derive(Debug, Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<Snapshot>,
+    acked: Option<u32>,
+}
+
+impl SnapshotHistory {
+    pub fn new() -> SnapshotHistory {
+        SnapshotHistory { snapshots: VecDeque::new(), acked: None }
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn get(&self, seq: u32) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|x| x.seq == seq)
+    }
+
+    // Deltas are only ever made against `seq` or later from now on, the
+    // snapshots before it can go. Unknown or older acknowledgements are
+    // ignored.
+    pub fn ack(&mut self, seq: u32) {
+        if self.get(seq).is_none() || self.acked.is_some_and(|acked| acked >= seq) {
+            return;
+        }
+        self.acked = Some(seq);
+        self.snapshots.retain(|x| x.seq >= seq);
+    }
+
+    // What to make the next delta against, None for the full state.
+    pub fn base(&self) -> Option<&Snapshot> {
+        self.acked.and_then(|seq| self.get(seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+    use crate::tests::{critter_at, player_at};
+
+    fn snapshot(seq: u32, players: Vec<Player>, critters: Vec<Critter>) -> Snapshot {
+        Snapshot { seq, players, critters }
+    }
+
+    #[test]
+    fn test_full_without_base() {
+        let critters = vec![critter_at(1, Pos::default(), 10), critter_at(2, Pos::default(), 10)];
+        let new = snapshot(1, vec![player_at(1, Pos::default())], critters);
+        let delta = new.delta(None);
+        assert_eq!(None, delta.base);
+        assert_eq!(2, delta.critters.len());
+        assert_eq!(Some(new), delta.apply(None));
+    }
+
+    #[test]
+    fn test_only_changes() {
+        let players = vec![player_at(1, Pos::default()), player_at(2, Pos::default())];
+        let critters = vec![critter_at(1, Pos::default(), 10), critter_at(2, Pos::default(), 10)];
+        let old = snapshot(1, players, critters);
+        let mut new = old.clone();
+        new.seq = 2;
+        new.players[1].pos = Pos::new(4.0, 0.0);
+        new.critters.remove(0);
+        new.critters.push(critter_at(3, Pos::new(5.0, 0.0), 10));
+
+        let delta = new.delta(Some(&old));
+        assert_eq!(Some(1), delta.base);
+        assert_eq!(vec![2], delta.players.iter().map(|x| x.id).collect::<Vec<_>>());
+        assert_eq!(Vec::<PlayerId>::new(), delta.removed_players);
+        assert_eq!(vec![critter_at(3, Pos::new(5.0, 0.0), 10)], delta.critters);
+        assert_eq!(vec![1], delta.removed_critters);
+        assert_eq!(Some(new), delta.apply(Some(&old)));
+    }
+
+    #[test]
+    fn test_apply_needs_base() {
+        let old = snapshot(1, vec![], vec![critter_at(1, Pos::default(), 10)]);
+        let new = snapshot(2, vec![], vec![critter_at(1, Pos::new(1.0, 0.0), 10)]);
+        let delta = new.delta(Some(&old));
+        assert_eq!(None, delta.clone().apply(None));
+        assert_eq!(None, delta.apply(Some(&new)));
+    }
+
+    #[test]
+    fn test_history_ack() {
+        let mut history = SnapshotHistory::new();
+        for seq in 1..=3 {
+            history.push(snapshot(seq, vec![], vec![]));
+        }
+        assert_eq!(None, history.base());
+        history.ack(2);
+        assert_eq!(Some(2), history.base().map(|x| x.seq));
+        assert!(history.get(1).is_none());
+
+        // Going back or acknowledging something never sent changes nothing.
+        history.ack(1);
+        history.ack(7);
+        assert_eq!(Some(2), history.base().map(|x| x.seq));
+    }
+
+    #[test]
+    fn test_history_falls_back_to_full() {
+        let mut history = SnapshotHistory::new();
+        history.push(snapshot(1, vec![], vec![]));
+        history.ack(1);
+        for seq in 2..=(SNAPSHOT_HISTORY as u32 + 1) {
+            history.push(snapshot(seq, vec![], vec![]));
+        }
+        assert_eq!(None, history.base());
+    }
+}
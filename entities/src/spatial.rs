@@ -0,0 +1,275 @@
+use crate::Pos;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Side of a grid cell, about the size of the biggest critters so that most
+// queries only have to look at a handful of cells.
+pub const CELL_SIZE: f32 = 100.0;
+
+type Cell = (i32, i32);
+
+#[derive(Debug, Clone, Copy)]
+struct Entry<K> {
+    key: K,
+    pos: Pos,
+    radius: f32,
+}
+
+// Uniform grid of circles. Each circle is filed under the cell its centre is
+// in, queries look `max_radius` further out to catch the big ones reaching
+// over from the cells around.
+#[derive(Debug, Clone)]
+pub struct SpatialGrid<K> {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<Entry<K>>>,
+    cell_of: HashMap<K, Cell>,
+    max_radius: f32,
+    // Cells that ever had something in them, so `nearest` knows when to
+    // stop looking.
+    bounds: Option<(Cell, Cell)>,
+}
+
+impl<K: Copy + Eq + Hash> Default for SpatialGrid<K> {
+    fn default() -> Self {
+        SpatialGrid::new(CELL_SIZE)
+    }
+}
+
+impl<K: Copy + Eq + Hash> SpatialGrid<K> {
+    pub fn new(cell_size: f32) -> SpatialGrid<K> {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+            cell_of: HashMap::new(),
+            max_radius: 0.0,
+            bounds: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cell_of.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cell_of.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.cell_of.clear();
+        self.max_radius = 0.0;
+        self.bounds = None;
+    }
+
+    fn cell(&self, x: f32, y: f32) -> Cell {
+        ((x / self.cell_size).floor() as i32, (y / self.cell_size).floor() as i32)
+    }
+
+    // Add a circle, or move it if `key` is already in the grid.
+    pub fn insert(&mut self, key: K, pos: Pos, radius: f32) {
+        let cell = self.cell(pos.x(), pos.y());
+        self.max_radius = self.max_radius.max(radius);
+        // Most moves stay within the cell.
+        if self.cell_of.get(&key) == Some(&cell) {
+            if let Some(entry) = self.cells.get_mut(&cell)
+                .and_then(|entries| entries.iter_mut().find(|entry| entry.key == key))
+            {
+                entry.pos = pos;
+                entry.radius = radius;
+                return;
+            }
+        }
+        self.remove(key);
+        self.cells.entry(cell).or_default().push(Entry { key, pos, radius });
+        self.cell_of.insert(key, cell);
+        self.bounds = Some(match self.bounds {
+            Some((low, high)) => ((low.0.min(cell.0), low.1.min(cell.1)), (high.0.max(cell.0), high.1.max(cell.1))),
+            None => (cell, cell),
+        });
+    }
+
+    pub fn remove(&mut self, key: K) -> bool {
+        let cell = match self.cell_of.remove(&key) {
+            Some(cell) => cell,
+            None => return false,
+        };
+        if let Some(entries) = self.cells.get_mut(&cell) {
+            entries.retain(|entry| entry.key != key);
+            if entries.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+        true
+    }
+
+    // Everything overlapping the circle, in no particular order.
+    pub fn query_circle(&self, center: Pos, radius: f32) -> Vec<K> {
+        let (min, max) = match self.bounds {
+            Some(bounds) => bounds,
+            None => return vec![],
+        };
+        // No need to look at cells that never had anything in them.
+        let reach = radius + self.max_radius;
+        let low = self.cell(center.x() - reach, center.y() - reach);
+        let high = self.cell(center.x() + reach, center.y() + reach);
+        let (low, high) = ((low.0.max(min.0), low.1.max(min.1)), (high.0.min(max.0), high.1.min(max.1)));
+        let mut found = vec![];
+        for x in low.0..=high.0 {
+            for y in low.1..=high.1 {
+                let entries = match self.cells.get(&(x, y)) {
+                    Some(entries) => entries,
+                    None => continue,
+                };
+                for entry in entries.iter() {
+                    let (dx, dy) = (entry.pos.x() - center.x(), entry.pos.y() - center.y());
+                    let limit = radius + entry.radius;
+                    if dx * dx + dy * dy <= limit * limit {
+                        found.push(entry.key);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    // The circle whose centre is closest to `pos`, and how far it is.
+    pub fn nearest(&self, pos: Pos) -> Option<(K, f32)> {
+        let (low, high) = self.bounds?;
+        let center = self.cell(pos.x(), pos.y());
+        // Beyond this ring there is nothing left to look at.
+        let last = (center.0 - low.0).abs()
+            .max((high.0 - center.0).abs())
+            .max((center.1 - low.1).abs())
+            .max((high.1 - center.1).abs());
+
+        let mut best: Option<(K, f32)> = None;
+        for ring in 0..=last {
+            for cell in ring_cells(center, ring) {
+                let entries = match self.cells.get(&cell) {
+                    Some(entries) => entries,
+                    None => continue,
+                };
+                for entry in entries.iter() {
+                    let (dx, dy) = (entry.pos.x() - pos.x(), entry.pos.y() - pos.y());
+                    let distance = (dx * dx + dy * dy).sqrt();
+                    if best.is_none_or(|(_, d)| distance < d) {
+                        best = Some((entry.key, distance));
+                    }
+                }
+            }
+            // Anything in the next ring is at least this far away.
+            if let Some((_, distance)) = best {
+                if distance <= ring as f32 * self.cell_size {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+// The cells exactly `ring` cells away from `center`, counting diagonals as
+// one.
+fn ring_cells(center: Cell, ring: i32) -> Vec<Cell> {
+    if ring == 0 {
+        return vec![center];
+    }
+    let mut cells = Vec::with_capacity(8 * ring as usize);
+    for i in -ring..=ring {
+        cells.push((center.0 + i, center.1 - ring));
+        cells.push((center.0 + i, center.1 + ring));
+    }
+    for i in (1 - ring)..ring {
+        cells.push((center.0 - ring, center.1 + i));
+        cells.push((center.0 + ring, center.1 + i));
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut keys: Vec<u32>) -> Vec<u32> {
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_query_circle() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, Pos::new(0.0, 0.0), 1.0);
+        grid.insert(2, Pos::new(15.0, 0.0), 1.0);
+        grid.insert(3, Pos::new(-40.0, -40.0), 1.0);
+        assert_eq!(vec![1, 2], sorted(grid.query_circle(Pos::new(5.0, 0.0), 9.0)));
+        assert_eq!(vec![3], grid.query_circle(Pos::new(-35.0, -35.0), 7.0));
+        assert!(grid.query_circle(Pos::new(100.0, 100.0), 5.0).is_empty());
+    }
+
+    #[test]
+    fn test_big_circles_reach_over() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, Pos::new(0.0, 0.0), 90.0);
+        assert_eq!(vec![1], grid.query_circle(Pos::new(95.0, 0.0), 6.0));
+    }
+
+    #[test]
+    fn test_query_outside_bounds() {
+        let mut grid = SpatialGrid::new(10.0);
+        assert!(grid.query_circle(Pos::new(0.0, 0.0), 100.0).is_empty());
+        grid.insert(1, Pos::new(0.0, 0.0), 1.0);
+        grid.insert(2, Pos::new(25.0, 25.0), 1.0);
+        assert!(grid.query_circle(Pos::new(-50.0, 0.0), 40.0).is_empty());
+        assert!(grid.query_circle(Pos::new(500.0, 500.0), 5.0).is_empty());
+        // Reaching in from far outside still finds what is there.
+        assert_eq!(vec![1], grid.query_circle(Pos::new(-50.0, 0.0), 49.5));
+        assert_eq!(vec![1, 2], sorted(grid.query_circle(Pos::new(-500.0, -500.0), 800.0)));
+        // Only the cells in use are looked at, this would take ages
+        // otherwise.
+        assert_eq!(vec![1, 2], sorted(grid.query_circle(Pos::new(0.0, 0.0), 1e7)));
+    }
+
+    #[test]
+    fn test_insert_moves() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, Pos::new(0.0, 0.0), 1.0);
+        grid.insert(1, Pos::new(50.0, 50.0), 1.0);
+        assert_eq!(1, grid.len());
+        assert!(grid.query_circle(Pos::new(0.0, 0.0), 5.0).is_empty());
+        assert!(grid.remove(1));
+        assert!(!grid.remove(1));
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn test_nearest() {
+        let mut grid = SpatialGrid::new(10.0);
+        assert_eq!(None, grid.nearest(Pos::new(0.0, 0.0)));
+        grid.insert(1, Pos::new(100.0, 0.0), 1.0);
+        grid.insert(2, Pos::new(-30.0, 40.0), 1.0);
+        grid.insert(3, Pos::new(12.0, 0.0), 1.0);
+        assert_eq!(Some((3, 12.0)), grid.nearest(Pos::new(0.0, 0.0)));
+        assert_eq!(Some((2, 0.0)), grid.nearest(Pos::new(-30.0, 40.0)));
+        assert_eq!(Some((1, 400.0)), grid.nearest(Pos::new(500.0, 0.0)));
+    }
+
+    #[test]
+    fn test_nearest_matches_linear_scan() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut grid = SpatialGrid::new(CELL_SIZE);
+        let mut points = vec![];
+        for key in 0..500u32 {
+            let pos = Pos::new(rng.gen_range(-1000.0, 1000.0), rng.gen_range(-1000.0, 1000.0));
+            grid.insert(key, pos, 10.0);
+            points.push(pos);
+        }
+        for _ in 0..50 {
+            let mut pos = Pos::new(rng.gen_range(-1500.0, 1500.0), rng.gen_range(-1500.0, 1500.0));
+            let expected = points.iter()
+                .map(|point| pos.object_distance(*point))
+                .fold(f32::MAX, f32::min);
+            assert_eq!(expected, grid.nearest(pos).unwrap().1);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{random_color, Critter, CritterId, GameWorld, Pos};
+use crate::behaviour::Behaviour;
+use rand::Rng;
+use std::env;
+use std::time::{Duration, Instant};
+
+// Tries to find a free spot for a critter before giving up until the next
+// tick.
+const ATTEMPTS: usize = 10;
+
+// A rectangle critters may appear in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpawnZone {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl SpawnZone {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> SpawnZone {
+        SpawnZone { x, y, width, height }
+    }
+
+    fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    fn random_pos<R: Rng>(&self, rng: &mut R) -> Pos {
+        Pos::new(
+            self.x + rng.gen_range(0.0, 1.0) * self.width,
+            self.y + rng.gen_range(0.0, 1.0) * self.height,
+        )
+    }
+
+    // "x,y,width,height"
+    fn parse(value: &str) -> Option<SpawnZone> {
+        let numbers: Vec<f32> = value.split(',')
+            .map(|x| x.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match numbers[..] {
+            [x, y, width, height] if width > 0.0 && height > 0.0 => Some(SpawnZone::new(x, y, width, height)),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnConfig {
+    // Below this many critters the world is filled up right away.
+    pub min_critters: usize,
+    // Above it, nothing spawns.
+    pub max_critters: usize,
+    // How often one critter is added in between.
+    pub interval: Duration,
+    // How close to a player a critter may appear.
+    pub clearance: f32,
+    // Anywhere in the world if empty.
+    pub zones: Vec<SpawnZone>,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        SpawnConfig {
+            min_critters: 40,
+            max_critters: 80,
+            interval: Duration::from_millis(500),
+            clearance: 100.0,
+            zones: vec![],
+        }
+    }
+}
+
+impl SpawnConfig {
+    // RUGAR_MIN_CRITTERS, RUGAR_MAX_CRITTERS and RUGAR_SPAWN_ZONES override
+    // the defaults. Zones are given as "x,y,width,height" separated by ";".
+    pub fn from_env() -> SpawnConfig {
+        let number = |name: &str| env::var(name).ok().and_then(|value| value.parse().ok());
+        let default = SpawnConfig::default();
+        let min_critters = number("RUGAR_MIN_CRITTERS").unwrap_or(default.min_critters);
+        let zones = env::var("RUGAR_SPAWN_ZONES").ok()
+            .and_then(|value| value.split(';').map(SpawnZone::parse).collect::<Option<Vec<_>>>())
+            .filter(|zones| !zones.is_empty())
+            .unwrap_or(default.zones);
+        SpawnConfig {
+            min_critters,
+            max_critters: number("RUGAR_MAX_CRITTERS").unwrap_or(default.max_critters).max(min_critters),
+            zones,
+            ..default
+        }
+    }
+}
+
+// Mostly food that wanders or runs away, some obstacles to grow into that
+// sit still or go after small players.
+pub fn random_critter(id: CritterId, pos: Pos) -> Critter {
+    let mut rng = rand::thread_rng();
+    let (size, behaviour) = if rng.gen_bool(0.7) {
+        let behaviour = if rng.gen_bool(0.5) { Behaviour::Wander } else { Behaviour::Flee };
+        (rng.gen_range(2, 8), behaviour)
+    } else {
+        let behaviour = if rng.gen_bool(0.7) { Behaviour::Idle } else { Behaviour::Chase };
+        (10 * rng.gen_range(1, 10), behaviour)
+    };
+    Critter {
+        id,
+        pos_x: pos.x(),
+        pos_y: pos.y(),
+        size,
+        color: random_color(),
+        behaviour,
+        home: pos,
+        ..Critter::default()
+    }
+}
+
+// Keeps the number of critters in the world within the configured bounds.
+#[derive(Debug)]
+pub struct Spawner {
+    config: SpawnConfig,
+    next_id: CritterId,
+    last_spawn: Option<Instant>,
+}
+
+impl Spawner {
+    pub fn new(config: SpawnConfig) -> Spawner {
+        Spawner { config, next_id: 1, last_spawn: None }
+    }
+
+    // The critters to add to `world` now.
+    pub fn spawn(&mut self, world: &GameWorld, now: Instant) -> Vec<Critter> {
+        let count = world.objects.len();
+        let wanted = if count < self.config.min_critters {
+            self.config.min_critters - count
+        } else if count < self.config.max_critters {
+            match self.last_spawn {
+                Some(last) if now.duration_since(last) < self.config.interval => 0,
+                _ => 1,
+            }
+        } else {
+            0
+        };
+        if wanted == 0 {
+            return vec![];
+        }
+
+        // Ids are never reused, clients would take a new critter for the
+        // old one moving.
+        self.next_id = self.next_id.max(world.objects.iter().map(|x| x.id + 1).max().unwrap_or(1));
+        let mut spawned: Vec<Critter> = vec![];
+        for _ in 0..wanted {
+            let critter = random_critter(self.next_id, Pos::default());
+            let pos = match self.free_pos(world, &spawned, critter.size as f32) {
+                Some(pos) => pos,
+                None => break,
+            };
+            self.next_id += 1;
+            spawned.push(Critter { pos_x: pos.x(), pos_y: pos.y(), home: pos, ..critter });
+        }
+        if !spawned.is_empty() {
+            self.last_spawn = Some(now);
+        }
+        spawned
+    }
+
+    // Somewhere in a zone, away from players and not on top of another
+    // critter. Zones reaching out of the world are cut off at its edge.
+    fn free_pos(&self, world: &GameWorld, spawned: &[Critter], size: f32) -> Option<Pos> {
+        let mut rng = rand::thread_rng();
+        let everywhere = [SpawnZone::new(0.0, 0.0, world.config.width, world.config.height)];
+        let zones = if self.config.zones.is_empty() { &everywhere[..] } else { &self.config.zones[..] };
+        let total: f32 = zones.iter().map(SpawnZone::area).sum();
+        for _ in 0..ATTEMPTS {
+            // Bigger zones get more critters.
+            let mut pick = rng.gen_range(0.0, 1.0) * total;
+            let zone = zones.iter()
+                .find(|zone| {
+                    pick -= zone.area();
+                    pick <= 0.0
+                })
+                .or_else(|| zones.last())?;
+            let pos = world.config.clamp(zone.random_pos(&mut rng), size);
+
+            // Those spawned this tick are not in the world yet.
+            let on_spawned = spawned.iter().any(|x| {
+                let reach = size + x.size as f32;
+                let (dx, dy) = (x.pos_x - pos.x(), x.pos_y - pos.y());
+                dx * dx + dy * dy <= reach * reach
+            });
+            if world.is_free(pos, size, self.config.clearance) && !on_spawned {
+                return Some(pos);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::player_at;
+    use crate::world::WorldConfig;
+
+    fn config() -> SpawnConfig {
+        SpawnConfig {
+            min_critters: 5,
+            max_critters: 7,
+            interval: Duration::from_millis(100),
+            clearance: 100.0,
+            zones: vec![],
+        }
+    }
+
+    fn world() -> GameWorld {
+        GameWorld::with_config(WorldConfig::new(2000.0, 2000.0))
+    }
+
+    #[test]
+    fn test_keeps_within_bounds() {
+        let start = Instant::now();
+        let mut world = world();
+        let mut spawner = Spawner::new(config());
+
+        let critters = spawner.spawn(&world, start);
+        assert_eq!(5, critters.len());
+        world.add_critters(critters);
+        // Then one at a time.
+        assert!(spawner.spawn(&world, start).is_empty());
+        world.add_critters(spawner.spawn(&world, start + Duration::from_millis(100)));
+        world.add_critters(spawner.spawn(&world, start + Duration::from_millis(200)));
+        assert_eq!(7, world.objects.len());
+        assert!(spawner.spawn(&world, start + Duration::from_secs(1)).is_empty());
+
+        // Ids stay unique after some were eaten.
+        world.objects.truncate(2);
+        world.reindex();
+        world.add_critters(spawner.spawn(&world, start + Duration::from_secs(2)));
+        let mut ids: Vec<CritterId> = world.objects.iter().map(|x| x.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(5, ids.len());
+    }
+
+    #[test]
+    fn test_away_from_players_in_zones() {
+        let mut world = world();
+        world.add_player(player_at(1, Pos::new(50.0, 50.0)));
+        let mut config = config();
+        config.min_critters = 50;
+        config.zones = vec![SpawnZone::new(0.0, 0.0, 300.0, 300.0), SpawnZone::new(1000.0, 0.0, 300.0, 300.0)];
+
+        for critter in Spawner::new(config.clone()).spawn(&world, Instant::now()) {
+            let pos = Pos::new(critter.pos_x, critter.pos_y);
+            assert!(config.zones.iter().any(|z| pos.x() >= z.x && pos.x() <= z.x + z.width && pos.y() >= z.y && pos.y() <= z.y + z.height));
+            assert!(world.players_in(pos, config.clearance + critter.size as f32).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_parse_zone() {
+        assert_eq!(Some(SpawnZone::new(1.0, 2.0, 3.0, 4.0)), SpawnZone::parse("1, 2,3,4"));
+        assert_eq!(None, SpawnZone::parse("1,2,3"));
+        assert_eq!(None, SpawnZone::parse("1,2,0,4"));
+        assert_eq!(None, SpawnZone::parse("a,2,3,4"));
+    }
+
+    #[test]
+    fn test_inside_the_world() {
+        let world = GameWorld::with_config(WorldConfig::new(300.0, 200.0));
+        let mut config = config();
+        config.min_critters = 30;
+        config.zones = vec![SpawnZone::new(100.0, 100.0, 1000.0, 1000.0)];
+        for critter in Spawner::new(config).spawn(&world, Instant::now()) {
+            let (x, y, size) = (critter.pos_x, critter.pos_y, critter.size as f32);
+            assert!(x >= 100.0 && x + size <= 300.0 && y >= 100.0 && y + size <= 200.0);
+        }
+    }
+}
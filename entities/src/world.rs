@@ -0,0 +1,89 @@
+use crate::{Player, Pos};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::env;
+
+// The playing field, from (0, 0) to (width, height). Nothing leaves it.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct WorldConfig {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig { width: 800.0, height: 800.0 }
+    }
+}
+
+impl WorldConfig {
+    pub fn new(width: f32, height: f32) -> WorldConfig {
+        WorldConfig { width, height }
+    }
+
+    // RUGAR_WORLD_WIDTH and RUGAR_WORLD_HEIGHT override the defaults.
+    pub fn from_env() -> WorldConfig {
+        let length = |name: &str, default: f32| env::var(name).ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|value: &f32| *value > 0.0)
+            .unwrap_or(default);
+        let default = WorldConfig::default();
+        WorldConfig {
+            width: length("RUGAR_WORLD_WIDTH", default.width),
+            height: length("RUGAR_WORLD_HEIGHT", default.height),
+        }
+    }
+
+    pub fn random_pos(&self) -> Pos {
+        let mut rng = rand::thread_rng();
+        Pos::new(rng.gen_range(0.0, 1.0) * self.width, rng.gen_range(0.0, 1.0) * self.height)
+    }
+
+    // The closest position to `pos` where a circle of `radius` is entirely
+    // inside. Circles too big for the world are kept in the middle.
+    pub fn clamp(&self, pos: Pos, radius: f32) -> Pos {
+        let axis = |value: f32, length: f32| if 2.0 * radius >= length {
+            length / 2.0
+        } else {
+            value.clamp(radius, length - radius)
+        };
+        Pos::new(axis(pos.x(), self.width), axis(pos.y(), self.height))
+    }
+
+    // Stop a player at the edge, it keeps sliding along it.
+    pub fn keep_inside(&self, player: &mut Player) {
+        let pos = self.clamp(player.pos, player.size);
+        if pos.x() != player.pos.x() {
+            player.vel_x = 0.0;
+        }
+        if pos.y() != player.pos.y() {
+            player.vel_y = 0.0;
+        }
+        player.pos = pos;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp() {
+        let world = WorldConfig::new(100.0, 50.0);
+        assert_eq!(Pos::new(30.0, 20.0), world.clamp(Pos::new(30.0, 20.0), 10.0));
+        assert_eq!(Pos::new(10.0, 40.0), world.clamp(Pos::new(-5.0, 60.0), 10.0));
+        assert_eq!(Pos::new(70.0, 25.0), world.clamp(Pos::new(80.0, 0.0), 30.0));
+    }
+
+    #[test]
+    fn test_keep_inside() {
+        let world = WorldConfig::new(100.0, 100.0);
+        let mut player = Player::new();
+        player.pos = Pos::new(95.0, 50.0);
+        player.vel_x = 10.0;
+        player.vel_y = 10.0;
+        world.keep_inside(&mut player);
+        assert_eq!(Pos::new(90.0, 50.0), player.pos);
+        assert_eq!((0.0, 10.0), (player.vel_x, player.vel_y));
+    }
+}
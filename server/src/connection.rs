@@ -0,0 +1,212 @@
+use entities::codec::Encoding;
+use entities::frame;
+use entities::heartbeat::Heartbeat;
+use entities::interest;
+use entities::outbound::{OutboundQueue, OVERLOAD_GRACE};
+use entities::snapshot::SnapshotHistory;
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+use std::io;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+// Everything known about a connection once its handshake is done.
+pub struct Client {
+    pub id: entities::PlayerId,
+    pub name: String,
+    pub encoding: Encoding,
+    pub heartbeat: Heartbeat,
+    pub resume_token: u64,
+    // What we sent recently, to make deltas against.
+    pub snapshots: SnapshotHistory,
+    // Who the player can see.
+    pub interest: interest::Interest,
+}
+
+// Messages are only encoded once the bytes before them are this close to
+// being written, the rest wait in the queue where they can still be
+// coalesced.
+const WRITE_BUFFER: usize = 16 * 1024;
+
+// A non-blocking socket with its own read and write buffers. Nothing in here
+// ever waits on the network, whatever can't be written right away is kept
+// until the socket becomes writable again.
+pub struct Connection {
+    pub socket: TcpStream,
+    pub token: Token,
+    pub opened: Instant,
+    // None until the handshake is done.
+    pub client: Option<Client>,
+    // Close once everything queued so far has been written.
+    pub closing: bool,
+    pub closed: bool,
+    // The other side hung up, the next `read_frames` says so.
+    pub eof: bool,
+    decoder: frame::FrameDecoder,
+    queue: OutboundQueue,
+    outgoing: Vec<u8>,
+    // Since when the socket has not taken everything we had for it.
+    stalled_since: Option<Instant>,
+    interest: Interest,
+}
+
+impl Connection {
+    pub fn new(socket: TcpStream, token: Token, now: Instant) -> Connection {
+        Connection {
+            socket,
+            token,
+            opened: now,
+            client: None,
+            closing: false,
+            closed: false,
+            eof: false,
+            decoder: frame::FrameDecoder::new(),
+            queue: OutboundQueue::new(),
+            outgoing: vec![],
+            stalled_since: None,
+            interest: Interest::READABLE,
+        }
+    }
+
+    pub fn register(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.register(&mut self.socket, self.token, self.interest)
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.deregister(&mut self.socket)
+    }
+
+    pub fn name(&self) -> String {
+        match self.client {
+            Some(ref client) => format!("{} ({})", client.name, client.id),
+            None => format!("connection {}", self.token.0),
+        }
+    }
+
+    // Read everything the socket has for us and return the complete frames.
+    // Fails once the other side is gone or sent something that is not a
+    // frame. Frames that came in right before the other side hung up are
+    // still returned, it fails on the call after that.
+    pub fn read_frames(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        if self.eof {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        let mut buf = [0; 4096];
+        loop {
+            match self.socket.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    break
+                },
+                Ok(n) => self.decoder.extend(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let mut frames = vec![];
+        while let Some(frame) = self.decoder.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    pub fn send(&mut self, message: &entities::Message) {
+        self.queue.push(message.clone(), Instant::now());
+    }
+
+    // Encode right away and skip the queue, for the handshake which has to
+    // go out before anything else and in its own encoding.
+    pub fn send_as(&mut self, encoding: Encoding, message: &entities::Message) {
+        let payload = encoding.encode(message).unwrap();
+        self.outgoing.extend(frame::encode(&payload));
+    }
+
+    // Whether the client has been too slow to read what we send for too
+    // long. Coalescing keeps the queue short for a client that reads
+    // nothing at all, so a socket that stays full counts as well.
+    pub fn overloaded(&self, now: Instant) -> bool {
+        let stalled = match self.stalled_since {
+            Some(since) => now.duration_since(since) > OVERLOAD_GRACE,
+            None => false,
+        };
+        stalled || self.queue.overloaded(now)
+    }
+
+    // Messages go out in the negotiated encoding, or as JSON while the
+    // handshake is still going on.
+    fn encode_queued(&mut self) {
+        let encoding = match self.client {
+            Some(ref client) => client.encoding,
+            None => Encoding::Json,
+        };
+        while self.outgoing.len() < WRITE_BUFFER {
+            let message = match self.queue.pop() {
+                Some(message) => message,
+                None => break,
+            };
+            let payload = encoding.encode(&message).unwrap();
+            self.outgoing.extend(frame::encode(&payload));
+        }
+    }
+
+    // Write as much as the socket takes and only ask to be woken up for
+    // writing while something is left over.
+    pub fn flush(&mut self, registry: &Registry) -> io::Result<()> {
+        self.encode_queued();
+        while !self.outgoing.is_empty() {
+            match self.socket.write(&self.outgoing) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "connection closed")),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                    self.encode_queued();
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.outgoing.is_empty() {
+            self.stalled_since = None;
+        } else if self.stalled_since.is_none() {
+            self.stalled_since = Some(Instant::now());
+        }
+        if self.closing && self.outgoing.is_empty() {
+            self.closed = true;
+            return Ok(());
+        }
+
+        // encode_queued refills the buffer as long as there is anything
+        // queued, so an empty buffer means we're done.
+        let interest = if self.outgoing.is_empty() {
+            Interest::READABLE
+        } else {
+            Interest::READABLE | Interest::WRITABLE
+        };
+        if interest != self.interest {
+            self.interest = interest;
+            registry.reregister(&mut self.socket, self.token, interest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net;
+
+    #[test]
+    fn test_frames_before_eof() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(&frame::encode(b"last words")).unwrap();
+        drop(client);
+        // Left blocking, so the frame and the close are read in one go.
+        let (socket, _) = listener.accept().unwrap();
+        let mut connection = Connection::new(TcpStream::from_std(socket), Token(0), Instant::now());
+
+        assert_eq!(vec![b"last words".to_vec()], connection.read_frames().unwrap());
+        assert_eq!(io::ErrorKind::UnexpectedEof, connection.read_frames().unwrap_err().kind());
+    }
+}
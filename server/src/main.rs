@@ -1,147 +1,404 @@
-use std::net::{TcpListener, TcpStream};
 use std::io;
-use std::thread;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
-use entities;
-use serde::{Deserialize};
-use std::sync::{ Arc, Mutex };
+use entities::codec::Encoding;
+use entities::handshake;
+use entities::heartbeat::{Heartbeat, HeartbeatConfig};
+use entities::interest::{self, InterestConfig};
+use entities::snapshot::SnapshotHistory;
+use entities::spawner::{SpawnConfig, Spawner};
+use entities::world::WorldConfig;
+use mio::net::TcpListener;
+use mio::{Events, Interest, Poll, Token};
 use std::vec::Vec;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-struct Client {
-    pub socket: TcpStream,
-    pub name: String,
+mod connection;
+
+use connection::{Client, Connection};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a player who lost the connection can come back to where they were.
+const SESSION_GRACE: Duration = Duration::from_secs(60);
+const LISTENER: Token = Token(0);
+
+// A player waiting for its client to reconnect.
+struct Session {
+    player: entities::Player,
+    expires: Instant,
 }
 
+// All connections are served from a single thread: the poll loop reads
+// whatever arrived, runs the simulation whenever a tick is due and writes
+// out as much as every socket accepts without blocking.
 struct Application {
-    clients: Arc<Mutex<Vec<Client>>>,
-    listeners: std::vec::Vec<std::thread::JoinHandle<()>>,
-    receiver: Arc<Mutex<Receiver<String>>>,
-    sender: Sender<String>,
+    poll: Poll,
+    listener: TcpListener,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
     world_state: entities::GameWorld,
+    next_id: entities::PlayerId,
+    heartbeat: HeartbeatConfig,
+    interest: InterestConfig,
+    sessions: HashMap<u64, Session>,
+    inputs: HashMap<entities::PlayerId, entities::input::InputQueue>,
+    snapshot_seq: u32,
+    spawner: Spawner,
 }
 
-fn start_listening(stream : Receiver<TcpStream>, sender : Sender<String>) {
-    let client = stream.recv().expect("Error TcpStream received invalid");
-    loop {
-        let mut de = serde_json::Deserializer::from_reader(&client);
-        if let Ok(payload1) = entities::Message::deserialize(&mut de) {
-            sender.send(serde_json::to_string(&payload1).unwrap()).unwrap();
+impl Application {
+    fn new(listener: TcpListener) -> io::Result<Application> {
+        let mut app = Application {
+            poll: Poll::new()?,
+            listener,
+            connections: HashMap::new(),
+            next_token: LISTENER.0 + 1,
+            world_state: entities::GameWorld::with_config(WorldConfig::from_env()),
+            next_id: 1,
+            heartbeat: HeartbeatConfig::from_env(),
+            interest: InterestConfig::from_env(),
+            sessions: HashMap::new(),
+            inputs: HashMap::new(),
+            snapshot_seq: 0,
+            spawner: Spawner::new(SpawnConfig::from_env()),
+        };
+        app.poll.registry().register(&mut app.listener, LISTENER, Interest::READABLE)?;
+        Ok(app)
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let tick = Duration::from_secs(1) / entities::TICK_RATE;
+        let mut next_tick = Instant::now() + tick;
+        let mut events = Events::with_capacity(1024);
+        loop {
+            let timeout = next_tick.saturating_duration_since(Instant::now());
+            if let Err(e) = self.poll.poll(&mut events, Some(timeout)) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.accept(),
+                    // Writable sockets are taken care of by flush below.
+                    token => if event.is_readable() {
+                        self.read(token)
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            if now >= next_tick {
+                self.tick(now);
+                next_tick += tick;
+                // Don't try to catch up after a stall, just carry on.
+                if next_tick < now {
+                    next_tick = now + tick;
+                }
+            }
+
+            self.flush();
         }
     }
-}
 
-fn write_to_client(client: &Client, message: &str) -> bool {
-    let message: entities::Message = serde_json::from_str(message).unwrap();
-    match message.mtype {
-        entities::MessageType::PlayerPosition => {
-            let player = message.player.unwrap();
-            if player.name != client.name {
-                let message = entities::Message::player_update(&player);
-                if serde_json::to_writer(&client.socket, &message).is_err() {
-                    println!("Could not write to {}", client.name);
-                    return false
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((socket, addr)) => {
+                    let token = Token(self.next_token);
+                    self.next_token += 1;
+                    let _ = socket.set_nodelay(true);
+                    let mut connection = Connection::new(socket, token, Instant::now());
+                    match connection.register(self.poll.registry()) {
+                        Ok(_) => {
+                            println!("New connection from {}", addr);
+                            self.connections.insert(token, connection);
+                        },
+                        Err(e) => println!("Could not register {}: {}", addr, e),
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("{}", e);
+                    break
                 }
             }
-        },
-        entities::MessageType::WorldState => {
-            if serde_json::to_writer(&client.socket, &message).is_err() {
-                println!("Could not write to {}", client.name);
-                return false
-            } else {
-                println!("Sent world state to {:?}", client.name);
+        }
+    }
+
+    fn read(&mut self, token: Token) {
+        loop {
+            let frames = match self.connections.get_mut(&token) {
+                Some(connection) => match connection.read_frames() {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        println!("Stopped listening to {}: {}", connection.name(), e);
+                        connection.closed = true;
+                        return
+                    }
+                },
+                None => return,
+            };
+            for payload in frames {
+                self.receive(token, &payload);
+            }
+            // What was sent right before hanging up is handled, the next
+            // read finds out the other side is gone.
+            match self.connections.get(&token) {
+                Some(connection) if connection.eof => continue,
+                _ => return,
             }
         }
     }
 
-    true
-}
+    fn receive(&mut self, token: Token, payload: &[u8]) {
+        let now = Instant::now();
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) if !connection.closing && !connection.closed => connection,
+            _ => return,
+        };
+        let client = match connection.client {
+            Some(ref mut client) => client,
+            None => return self.handshake(token, payload),
+        };
 
-impl Application {
-    fn add_client(&mut self, client : TcpStream) {
-        let stream_clone = client.try_clone().unwrap();
-
-        // Get the client's player name. This is used to prevent broadcasting
-        // movement messages to self.
-        let mut de = serde_json::Deserializer::from_reader(&client);
-        let payload1 = entities::Message::deserialize(&mut de).unwrap();
-
-        match payload1.mtype {
-            entities::MessageType::PlayerPosition => {
-                let player = payload1.player.unwrap();
-                let name: String = player.name.to_string();
-                let player_client = Client {
-                    socket: client,
-                    name
-                };
-
-                println!("New client connected {}", player.name);
-
-                // send world_state
-                write_to_client(
-                    &player_client,
-                    &serde_json::to_string(
-                        &entities::Message::world_update(&self.world_state)
-                        ).unwrap()
-                    );
-
-                self.clients.lock().unwrap().push(player_client);
+        let message = match client.encoding.decode(payload) {
+            Ok(message) => message,
+            // The frame boundary is intact, so just skip the bad message.
+            Err(e) => return println!("Invalid message from {}: {}", client.name, e),
+        };
+        client.heartbeat.seen(now);
+        match message.mtype {
+            entities::MessageType::Input(command) =>
+                self.inputs.entry(client.id).or_default().push(command),
+            entities::MessageType::Ping(stamp) =>
+                connection.send(&entities::Message::pong(stamp)),
+            entities::MessageType::Pong(stamp) =>
+                client.heartbeat.pong(stamp, now),
+            entities::MessageType::SnapshotAck(seq) =>
+                client.snapshots.ack(seq),
+            _ => ()
+        }
+    }
+
+    // The first frame of a connection has to be a `Hello`, in JSON whatever
+    // encoding is picked afterwards.
+    fn handshake(&mut self, token: Token, payload: &[u8]) {
+        let hello = match Encoding::Json.decode(payload).map(|message| message.mtype) {
+            Ok(entities::MessageType::Hello(hello)) => hello,
+            _ => {
+                println!("Client did not say hello, dropping it");
+                self.connections.get_mut(&token).unwrap().closed = true;
+                return
+            }
+        };
+
+        // A client resuming before we noticed its old connection died
+        // replaces that connection.
+        if let Some(resume_token) = hello.resume_token {
+            let old = self.connections.values()
+                .find(|x| x.client.as_ref().map(|client| client.resume_token) == Some(resume_token))
+                .map(|x| x.token);
+            if let Some(old) = old {
+                self.remove_connection(old);
+            }
+        }
+        let resumed = hello.resume_token
+            .and_then(|resume_token| self.sessions.remove(&resume_token))
+            .map(|session| session.player);
+
+        // Inputs read from this connection are applied to the player with
+        // this id, whatever the client claims.
+        let id = match resumed {
+            Some(ref player) => player.id,
+            None => self.next_id,
+        };
+        let name = hello.display_name();
+        let resume_token = rand::random();
+        let (world, view_radius) = (self.world_state.config, self.interest.view_radius);
+        let connection = self.connections.get_mut(&token).unwrap();
+        let welcome = match handshake::negotiate(&hello, id, resume_token, world, view_radius) {
+            Ok(welcome) => welcome,
+            Err(reason) => {
+                println!("Rejected {}: {}", name, reason);
+                connection.send(&entities::Message::rejected(&reason));
+                connection.closing = true;
+                // Give the session back, the next attempt may do better.
+                if let (Some(player), Some(resume_token)) = (resumed, hello.resume_token) {
+                    self.keep_session(resume_token, player);
+                }
+                return
             },
-            _ => println!("Other type of message received, this shouldn't happen")
+        };
+        if resumed.is_none() {
+            self.next_id += 1;
         }
 
-        let (send, rec) = mpsc::channel();
-        let sender = self.sender.clone();
-        self.listeners.push(thread::spawn(move || start_listening(rec, sender)));
-        send.send(stream_clone).unwrap();
+        connection.send_as(Encoding::Json, &entities::Message::welcome(welcome.clone()));
+        let encoding = welcome.encoding;
+        connection.client = Some(Client {
+            id,
+            name: name.clone(),
+            encoding,
+            heartbeat: Heartbeat::new(self.heartbeat, Instant::now()),
+            resume_token,
+            snapshots: SnapshotHistory::new(),
+            interest: interest::Interest::new(),
+        });
+
+        // The server owns the player's state, only the name is taken from
+        // the client.
+        let mut new_player = match resumed {
+            Some(player) => {
+                println!("{} resumed as {} ({})", name, id, encoding.name());
+                player
+            },
+            None => {
+                println!("New client connected {} as {} ({})", name, id, encoding.name());
+                let mut player = entities::Player::new();
+                player.id = id;
+                player.pos = self.world_state.player_spawn_pos();
+                player
+            }
+        };
+        new_player.set_name(&name);
+        // The first snapshot on the next tick has everything else.
+        self.world_state.add_player(new_player);
     }
 
-    fn process(&self) {
-        let cloned_rec = Arc::clone(&self.receiver);
-        let cloned_clients = self.clients.clone();
-        thread::spawn(move || {
-            let mut dropouts = HashSet::new();
-            loop {
-                match cloned_rec.lock().unwrap().try_recv() {
-                    Ok(d) => {
-                        for client in &*cloned_clients.lock().unwrap() {
-                            if !dropouts.contains(&client.name) &&
-                               !write_to_client(client, &d) {
-                                dropouts.insert(client.name.clone());
-                            }
-                        }
-                    },
-                    Err(_e) => {},
+    fn tick(&mut self, now: Instant) {
+        // Each player gets about one queued input per tick, anything above
+        // that waits or is dropped, so nobody moves faster than the clock.
+        for (id, queue) in self.inputs.iter_mut() {
+            for command in queue.take() {
+                self.world_state.apply_input(*id, &command);
+            }
+        }
+
+        self.world_state.separate_players();
+        self.world_state.move_critters();
+
+        // Whatever was eaten and how much everyone grew goes out with the
+        // snapshots below.
+        for (id, meal) in self.world_state.eat() {
+            if let entities::Meal::Player(prey) = meal {
+                println!("{} ate {}", id, prey);
+            }
+        }
+        let critters = self.spawner.spawn(&self.world_state, now);
+        self.world_state.add_critters(critters);
+
+        self.snapshot_seq += 1;
+        let (world, seq, view_radius) = (&self.world_state, self.snapshot_seq, self.interest.view_radius);
+
+        for connection in self.connections.values_mut() {
+            if connection.overloaded(now) {
+                println!("{} can't keep up", connection.name());
+                connection.closed = true;
+                continue
+            }
+            let client = match connection.client {
+                Some(ref mut client) => client,
+                None => {
+                    if now.duration_since(connection.opened) > HANDSHAKE_TIMEOUT {
+                        println!("{} did not say hello in time", connection.name());
+                        connection.closed = true;
+                    }
+                    continue
                 }
+            };
+            // Half-open connections never fail a write, only the missing
+            // answers to our pings give them away.
+            if client.heartbeat.timed_out(now) {
+                println!("{} ({}) timed out", client.name, client.id);
+                connection.closed = true;
+                continue
+            }
+            // Only what is around the player and changed since the last
+            // snapshot the client confirmed, or everything around it if it
+            // hasn't confirmed one for a while.
+            let (visible, events) = client.interest.filter(world, seq, client.id, view_radius);
+            let delta = visible.delta(client.snapshots.base());
+            client.snapshots.push(visible);
+            let ping = client.heartbeat.ping_due(now);
+            for event in events {
+                connection.send(&entities::Message::interest(event));
+            }
+            connection.send(&entities::Message::snapshot(delta));
+            if let Some(stamp) = ping {
+                connection.send(&entities::Message::ping(stamp));
             }
+        }
+
+        self.sessions.retain(|_, session| session.expires > now);
+    }
+
+    // Write out what was queued and get rid of the connections that are done.
+    fn flush(&mut self) {
+        let registry = self.poll.registry();
+        for connection in self.connections.values_mut() {
+            if connection.closed {
+                continue
+            }
+            if let Err(e) = connection.flush(registry) {
+                println!("Could not write to {}: {}", connection.name(), e);
+                connection.closed = true;
+            }
+        }
+
+        let closed: Vec<Token> = self.connections.values()
+            .filter(|connection| connection.closed)
+            .map(|connection| connection.token)
+            .collect();
+        for token in closed {
+            self.remove_connection(token);
+        }
+    }
+
+    fn keep_session(&mut self, resume_token: u64, player: entities::Player) {
+        self.sessions.insert(resume_token, Session {
+            player,
+            expires: Instant::now() + SESSION_GRACE,
         });
     }
-}
 
-fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:3012")?;
-    let (send, rec) : (Sender<String>, Receiver<String>) = mpsc::channel();
-    let mut app = Application {
-        clients: Arc::new(Mutex::new(Vec::new())),
-        listeners: Vec::new(),
-        receiver: Arc::new(Mutex::new(rec)),
-        sender: send,
-        world_state: entities::GameWorld::new(),
-    };
-
-    app.process();
-
-    // accept connections and process them serially
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                app.add_client(stream);
-                println!("added client");
-            }
-            Err(e) => { println!("{}", e) }
+    // Forget everything about a connection and tell the others its player
+    // is gone. The player itself is kept around for a while in case the
+    // client resumes.
+    fn remove_connection(&mut self, token: Token) {
+        let mut connection = match self.connections.remove(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let _ = connection.deregister(self.poll.registry());
+        let client = match connection.client {
+            Some(client) => client,
+            None => return,
+        };
+
+        let id = client.id;
+        if let Some(player) = self.world_state.players.iter().find(|x| x.id == id) {
+            let player = player.clone();
+            self.keep_session(client.resume_token, player);
+        }
+        self.world_state.remove_player(id);
+        self.inputs.remove(&id);
+        println!("{} ({}) left", client.name, id);
+
+        let message = entities::Message::player_left(id);
+        for connection in self.connections.values_mut() {
+            if connection.client.is_some() {
+                connection.send(&message);
+            }
         }
     }
+}
+
+fn main() -> io::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:3012".parse().unwrap())?;
+    let mut app = Application::new(listener)?;
+    app.run()?;
     println!("done");
     Ok(())
 }